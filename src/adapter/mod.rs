@@ -1,4 +1,5 @@
-use {Address, AddressBusIO, As, Data};
+use std::mem;
+use {Address, AddressBusIO, As, BusError, Data};
 
 pub struct BusAdapter<'a, T: Address, U: Data> {
     connection: &'a mut dyn AddressBusIO<T, U>,
@@ -19,6 +20,129 @@ impl<'a, T: Address + As<V>, U: Data + As<Z>, V: Address + As<T>, Z: Data + As<U
     fn write(&mut self, address: T, value: U) {
         self.connection.write(address.as_(), value.as_())
     }
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        Ok(self.connection.try_read(address.as_())?.as_())
+    }
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.connection.try_write(address.as_(), value.as_())
+    }
+    fn take_wait_states(&mut self) -> u64 {
+        self.connection.take_wait_states()
+    }
+}
+
+pub struct BusAdapterBoxed<T: Address, U: Data> {
+    connection: Box<dyn AddressBusIO<T, U>>,
+}
+
+impl<T: Address, U: Data> BusAdapterBoxed<T, U> {
+    pub fn new(bus: Box<dyn AddressBusIO<T, U>>) -> BusAdapterBoxed<T, U> {
+        BusAdapterBoxed { connection: bus }
+    }
+}
+
+impl<T: Address + As<V>, U: Data + As<Z>, V: Address + As<T>, Z: Data + As<U>> AddressBusIO<T, U>
+    for BusAdapterBoxed<V, Z>
+{
+    fn read(&mut self, address: T) -> U {
+        self.connection.read(address.as_()).as_()
+    }
+    fn write(&mut self, address: T, value: U) {
+        self.connection.write(address.as_(), value.as_())
+    }
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        Ok(self.connection.try_read(address.as_())?.as_())
+    }
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.connection.try_write(address.as_(), value.as_())
+    }
+    fn take_wait_states(&mut self) -> u64 {
+        self.connection.take_wait_states()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+// splits each access of a wide data bus into multiple byte accesses
+pub struct ByteLaneAdapter<'a, T: Address> {
+    connection: &'a mut dyn AddressBusIO<T, u8>,
+    pub endianness: Endianness,
+    // bit N enables lane N (lane 0 is the least significant byte)
+    pub byte_enable: u8,
+}
+
+impl<'a, T: Address> ByteLaneAdapter<'a, T> {
+    pub fn new(
+        bus: &'a mut dyn AddressBusIO<T, u8>,
+        endianness: Endianness,
+    ) -> ByteLaneAdapter<'a, T> {
+        ByteLaneAdapter {
+            connection: bus,
+            endianness,
+            byte_enable: 0xff,
+        }
+    }
+
+    fn lane_offset(&self, lane: usize, lanes: usize) -> T {
+        match self.endianness {
+            Endianness::Little => T::from(lane).unwrap(),
+            Endianness::Big => T::from(lanes - 1 - lane).unwrap(),
+        }
+    }
+}
+
+impl<'a, T: Address + As<V>, U: Data + As<u8>, V: Address> AddressBusIO<T, U>
+    for ByteLaneAdapter<'a, V>
+where
+    u8: As<U>,
+{
+    fn read(&mut self, address: T) -> U {
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn write(&mut self, address: T, value: U) {
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
+        }
+    }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let lanes = mem::size_of::<U>();
+        let mut value = U::zero();
+        for lane in 0..lanes {
+            if self.byte_enable & (1 << lane) == 0 {
+                continue;
+            }
+            let offset = self.lane_offset(lane, lanes);
+            let byte: U = self.connection.try_read(address.as_() + offset)?.as_();
+            value = value | (byte << (lane * 8));
+        }
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        let lanes = mem::size_of::<U>();
+        for lane in 0..lanes {
+            if self.byte_enable & (1 << lane) == 0 {
+                continue;
+            }
+            let offset = self.lane_offset(lane, lanes);
+            let byte: u8 = (value >> (lane * 8)).as_();
+            self.connection.try_write(address.as_() + offset, byte)?;
+        }
+        Ok(())
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        self.connection.take_wait_states()
+    }
 }
 
 #[cfg(test)]
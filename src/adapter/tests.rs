@@ -1,4 +1,5 @@
-use adapter::BusAdapter;
+use adapter::{BusAdapter, ByteLaneAdapter, Endianness};
+use ram::Ram;
 use {Address, AddressBusIO, Data};
 
 struct TestAddressBusIO<T: Address, U: Data> {
@@ -28,3 +29,41 @@ fn converto_to_lower() {
         1
     );
 }
+
+#[test]
+fn byte_lanes_little_endian() {
+    let mut ram: Ram<u8> = Ram::new(16);
+    {
+        let mut adapter: ByteLaneAdapter<u16> = ByteLaneAdapter::new(&mut ram, Endianness::Little);
+        <dyn AddressBusIO<u32, u32>>::write(&mut adapter, 4, 0xaabbccdd);
+        assert_eq!(<dyn AddressBusIO<u32, u16>>::read(&mut adapter, 5), 0xbbcc);
+    }
+    assert_eq!(ram.read(4u16), 0xdd);
+    assert_eq!(ram.read(7u16), 0xaa);
+}
+
+#[test]
+fn byte_lanes_big_endian() {
+    let mut ram: Ram<u8> = Ram::new(16);
+    {
+        let mut adapter: ByteLaneAdapter<u16> = ByteLaneAdapter::new(&mut ram, Endianness::Big);
+        <dyn AddressBusIO<u16, u16>>::write(&mut adapter, 2, 0x1234);
+        assert_eq!(<dyn AddressBusIO<u16, u16>>::read(&mut adapter, 2), 0x1234);
+    }
+    assert_eq!(ram.read(2u16), 0x12);
+    assert_eq!(ram.read(3u16), 0x34);
+}
+
+#[test]
+fn byte_lanes_enable() {
+    let mut ram: Ram<u8> = Ram::new(16);
+    ram.fill(vec![0x11, 0x22], 0);
+    {
+        let mut adapter: ByteLaneAdapter<u16> = ByteLaneAdapter::new(&mut ram, Endianness::Little);
+        adapter.byte_enable = 0x02;
+        <dyn AddressBusIO<u16, u16>>::write(&mut adapter, 0, 0xaabb);
+        assert_eq!(<dyn AddressBusIO<u16, u16>>::read(&mut adapter, 0), 0xaa00);
+    }
+    assert_eq!(ram.read(0u16), 0x11);
+    assert_eq!(ram.read(1u16), 0xaa);
+}
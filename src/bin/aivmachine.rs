@@ -5,32 +5,39 @@ use clap::{App, Arg};
 
 use impostor::adapter::BusAdapter;
 use impostor::audio::Piano;
-use impostor::memcontroller::MemoryControllerSmart;
+use impostor::memcontroller::{MemoryControllerShared, MemoryControllerSmart};
 use impostor::mos6502::MOS6502;
-use impostor::ram::Ram;
+use impostor::ram::{BatteryRam, Ram};
 use impostor::random::Random;
 use impostor::rom::Rom;
+use impostor::scheduler::{Scheduler, Slot};
+use impostor::symbols::{self, SymbolTable};
 use impostor::unixterm::UnixTerm;
 
 use impostor::utils::to_number;
-use impostor::Clock;
 
 use impostor::graphics::vga_mode13h_palette::MODE13H_PALETTE;
 use impostor::graphics::{Framebuffer, Screen, WindowEvent};
+use impostor::i2c::eeprom::Eeprom24;
+use impostor::i2c::I2cBus;
 use impostor::input::{ElementState, VirtualKeyCode};
+use impostor::loader::Format;
 
-use impostor::dma::DmaBlock;
-use impostor::storage::BlockDevice;
+use impostor::dma::{DmaBlock, DmaMemory};
+use impostor::storage::{BlockDevice, FileStorage, OverlayStorage};
 use impostor::AddressBusIO;
 use impostor::Debug;
 use impostor::Interrupt;
 
-use impostor::debugger::debugger;
+use impostor::debugger::{debugger, watch_hit_str, Breakpoints, Stepper, WatchedBus, Watchpoints};
 
 use std::cell::RefCell;
 use std::fs;
+use std::process;
 use std::rc::Rc;
 
+const VBLANK: usize = 0;
+
 #[derive(Copy, Clone)]
 struct Sprite {
     tile: u8,
@@ -66,6 +73,7 @@ struct AivFrameBuffer {
     background_mode: u8,
     sprites: [Sprite; 64],
     chr_ram: [u8; 256 * 256],
+    chr_increment: u8,
 }
 
 impl AivFrameBuffer {
@@ -87,6 +95,7 @@ impl AivFrameBuffer {
             background_mode: 0,
             sprites: [sprite; 64],
             chr_ram: [0; 256 * 256],
+            chr_increment: 0,
         }
     }
 
@@ -327,9 +336,17 @@ impl AddressBusIO<u16, u8> for AivFrameBuffer {
             0x1103 => self.current_col = value,
             0x1104 => self.current_row = value,
             0x1105 => {
-                self.chr_ram[self.current_row as usize * 256 + self.current_col as usize] = value
+                self.chr_ram[self.current_row as usize * 256 + self.current_col as usize] = value;
+                // auto increment allows streaming the chr ram through the port (e.g. via dma)
+                if self.chr_increment & 0x01 == 1 {
+                    self.current_col = self.current_col.wrapping_add(1);
+                    if self.current_col == 0 {
+                        self.current_row = self.current_row.wrapping_add(1);
+                    }
+                }
             }
             0x1107 => self.background_mode = value,
+            0x1108 => self.chr_increment = value,
             _ => (),
         }
     }
@@ -362,12 +379,19 @@ impl AddressBusIO<u16, u8> for AivFrameBuffer {
             0x1105 => self.chr_ram[self.current_row as usize * 256 + self.current_col as usize],
             0x1106 => self.input,
             0x1107 => self.background_mode,
+            0x1108 => self.chr_increment,
             _ => 0,
         }
     }
 }
 
 fn main() {
+    process::exit(run());
+}
+
+// returns the exit code once storage and nvram are written back, devices
+// saving on drop are dropped before the process exits
+fn run() -> i32 {
     let matches = App::new("aivmachine")
         .version("0.1")
         .author("Roberto De Ioris <roberto@aiv01.it>")
@@ -423,6 +447,15 @@ fn main() {
                 .help("set duration of a piano note")
                 .default_value("125"),
         )
+        .arg(
+            Arg::with_name("vram-wait-states")
+                .required(false)
+                .long("vram-wait-states")
+                .takes_value(true)
+                .value_name("ticks")
+                .help("set the extra ticks for each access to the video ram")
+                .default_value("0"),
+        )
         .arg(
             Arg::with_name("chr-rom")
                 .required(false)
@@ -439,6 +472,52 @@ fn main() {
                 .value_name("file")
                 .help("attach a file-backed block device"),
         )
+        .arg(
+            Arg::with_name("storage-overlay")
+                .required(false)
+                .long("storage-overlay")
+                .help("keep storage writes in memory, leaving the file untouched"),
+        )
+        .arg(
+            Arg::with_name("eeprom")
+                .required(false)
+                .long("eeprom")
+                .takes_value(true)
+                .value_name("file")
+                .help("attach a file-backed 24C256 eeprom to the i2c bus"),
+        )
+        .arg(
+            Arg::with_name("nvram")
+                .required(false)
+                .long("nvram")
+                .takes_value(true)
+                .value_name("file")
+                .help("attach 2KiB of battery-backed ram at $3000, saved to the file"),
+        )
+        .arg(
+            Arg::with_name("power-on")
+                .required(false)
+                .long("power-on")
+                .takes_value(true)
+                .value_name("fill")
+                .help("set the power on content of the ram: zero, random or a byte value")
+                .default_value("zero"),
+        )
+        .arg(
+            Arg::with_name("check-uninitialized")
+                .long("check-uninitialized")
+                .help("report reads of ram cells never written"),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .required(false)
+                .long("symbols")
+                .takes_value(true)
+                .value_name("file")
+                .help(
+                    "load a comma separated list of ca65 .dbg, VICE label or name = address files",
+                ),
+        )
         .arg(
             Arg::with_name("breakpoint")
                 .required(false)
@@ -452,7 +531,7 @@ fn main() {
 
     let romfile = matches.value_of("romfile").unwrap();
 
-    let pc: u16 = match to_number(matches.value_of("pc").unwrap()) {
+    let mut pc: u16 = match to_number(matches.value_of("pc").unwrap()) {
         Ok(value) => value,
         Err(_) => panic!("invalid address format for pc"),
     };
@@ -477,19 +556,91 @@ fn main() {
         Err(_) => panic!("invalid number format for piano-speed"),
     };
 
-    let mut breakpoints: Vec<u16> = Vec::new();
+    let vram_wait_states: u64 = match to_number(matches.value_of("vram-wait-states").unwrap()) {
+        Ok(value) => value,
+        Err(_) => panic!("invalid number format for vram-wait-states"),
+    };
+
+    let mut symbol_table = SymbolTable::new();
+    if matches.is_present("symbols") {
+        for symbols_file in matches.value_of("symbols").unwrap().split(',') {
+            match symbols::load_file(symbols_file) {
+                Ok(file_symbols) => symbol_table.merge(&file_symbols),
+                Err(err) => {
+                    eprintln!("unable to load symbols: {}", err);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut breakpoints: Breakpoints<u16> = Breakpoints::new();
     if matches.is_present("breakpoint") {
         let breakpoint_addresses = matches.value_of("breakpoint").unwrap().split(',');
         for breakpoint_address in breakpoint_addresses {
-            breakpoints.push(to_number(breakpoint_address).unwrap());
+            match symbol_table.resolve(breakpoint_address) {
+                Ok(address) => {
+                    breakpoints.add(address as u16, false);
+                }
+                Err(err) => panic!("invalid breakpoint: {}", err),
+            }
         }
     }
 
-    let mut rom = Rom::new(fs::read(romfile).unwrap());
+    let mut power_on_ram: Ram<u8> = Ram::new(4096);
+    match matches.value_of("power-on").unwrap() {
+        "zero" => (),
+        "random" => power_on_ram.randomize(),
+        pattern => match to_number(pattern) {
+            Ok(value) => power_on_ram.fill_pattern(value),
+            Err(_) => panic!("invalid power-on fill"),
+        },
+    }
+
+    let mut rom_data = fs::read(romfile).unwrap();
+    // toolchain formats are placed in ram below $C000 and in rom from $C000 up,
+    // their entry point replaces the default pc
+    if let Some(format) = Format::from_extension(romfile) {
+        let image = match format.parse(&rom_data) {
+            Ok(image) => image,
+            Err(err) => {
+                eprintln!("unable to load {}: {}", romfile, err);
+                process::exit(1);
+            }
+        };
+        let (ram_image, rom_image) = image.split(0xc000);
+        if let Err(err) = ram_image.load_into::<u16, _>(&mut power_on_ram) {
+            eprintln!("unable to load {}: {}", romfile, err);
+            process::exit(1);
+        }
+        rom_data = match rom_image.flatten(0xc000, 0x4000, 0xff) {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln!("unable to load {}: {}", romfile, err);
+                process::exit(1);
+            }
+        };
+        if let Some(entry) = image.entry {
+            if entry > 0xffff {
+                eprintln!(
+                    "unable to load {}: entry ${:X} out of range",
+                    romfile, entry
+                );
+                process::exit(1);
+            }
+            if matches.occurrences_of("pc") == 0 {
+                pc = entry as u16;
+            }
+        }
+    }
+    let rom = Rc::new(RefCell::new(Rom::new(rom_data)));
 
-    let ram = Rc::new(RefCell::new(Ram::new(4096)));
+    let check_uninitialized = matches.is_present("check-uninitialized");
+    power_on_ram.track_uninitialized(check_uninitialized);
+    let ram = Rc::new(RefCell::new(power_on_ram));
 
     let mut term8 = UnixTerm::new();
+    let exit_request = term8.defer_exit();
 
     let mut term = BusAdapter::new(&mut term8);
 
@@ -497,6 +648,23 @@ fn main() {
 
     let mut random = Random::new();
 
+    // SCL is bit 0 and SDA is bit 1 of the i2c register
+    let mut i2c = I2cBus::new();
+    let mut eeprom: Option<Rc<RefCell<Eeprom24>>> = None;
+    if matches.is_present("eeprom") {
+        let eeprom_file = matches.value_of("eeprom").unwrap();
+        let mut eeprom_24c256 = Eeprom24::new_24c256();
+        if let Err(err) = eeprom_24c256.persist(eeprom_file) {
+            eprintln!("unable to open eeprom {}: {}", eeprom_file, err);
+            process::exit(1);
+        }
+        // written back once per second
+        eeprom_24c256.flush_ticks = u64::from(hz);
+        let shared_eeprom = Rc::new(RefCell::new(eeprom_24c256));
+        i2c.attach(Box::new(shared_eeprom.clone()));
+        eeprom = Some(shared_eeprom);
+    }
+
     let screen = Screen::new("aivmachine", 512, 512);
 
     let framebuffer = Framebuffer::new(256, 256);
@@ -516,69 +684,232 @@ fn main() {
 
     let mut memory_controller = MemoryControllerSmart::new();
     let borrowed_ram = Rc::clone(&ram);
-    memory_controller.map_shared(0x0000, 0x1fff, borrowed_ram);
-    memory_controller.map(0x2000, 0x2003, &mut term);
+    let ram_mapping = memory_controller.map_shared(0x0000, 0x1fff, borrowed_ram);
+    memory_controller.set_name(ram_mapping, "ram");
+    let term_mapping = memory_controller.map(0x2000, 0x2003, &mut term);
+    memory_controller.set_name(term_mapping, "term");
 
-    memory_controller.map(0x2004, 0x2004, &mut piano);
+    let piano_mapping = memory_controller.map(0x2004, 0x2004, &mut piano);
+    memory_controller.set_name(piano_mapping, "piano");
 
-    memory_controller.map(0x2005, 0x2005, &mut random);
+    let random_mapping = memory_controller.map(0x2005, 0x2005, &mut random);
+    memory_controller.set_name(random_mapping, "random");
 
-    let borrowed_aiv_framebuffer = Rc::clone(&aiv_framebuffer);
-    memory_controller.map_shared(0x4000, 0x7fff, borrowed_aiv_framebuffer);
+    let i2c_mapping = memory_controller.map(0x2006, 0x2006, &mut i2c);
+    memory_controller.set_name(i2c_mapping, "i2c");
 
-    memory_controller.map(0xc000, 0xffff, &mut rom);
+    let borrowed_aiv_framebuffer = Rc::clone(&aiv_framebuffer);
+    let framebuffer_mapping =
+        memory_controller.map_shared(0x4000, 0x7fff, borrowed_aiv_framebuffer);
+    memory_controller.set_name(framebuffer_mapping, "framebuffer");
+    memory_controller.set_wait_states(0x4000, 0x7fff, vram_wait_states);
+
+    let rom_mapping = memory_controller.map_shared(0xc000, 0xffff, rom.clone());
+    memory_controller.set_name(rom_mapping, "rom");
+
+    // the memory dma sees ram, video and rom at the same addresses of the cpu
+    let mut dma_bus = MemoryControllerShared::new();
+    dma_bus.map(0x0000, 0x1fff, ram.clone());
+    dma_bus.map(0x4000, 0x7fff, aiv_framebuffer.clone());
+    dma_bus.map(0xc000, 0xffff, rom);
+    let dma_memory: Rc<RefCell<DmaMemory<u16>>> =
+        Rc::new(RefCell::new(DmaMemory::new(Rc::new(RefCell::new(dma_bus)))));
+    // the register layouts are documented in the README memory map
+    let dma_memory_mapping = memory_controller.map_shared(0x2020, 0x202e, dma_memory.clone());
+    memory_controller.set_name(dma_memory_mapping, "dma memory");
 
     let mut dma: Option<Rc<RefCell<DmaBlock<u16>>>> = None;
     let has_storage = matches.is_present("storage");
     if has_storage {
-        let block_device = BlockDevice::from_filename(matches.value_of("storage").unwrap(), 256);
-        let dma_block = Rc::new(RefCell::new(DmaBlock::new(block_device, ram)));
+        let storage = matches.value_of("storage").unwrap();
+        let block_device = if matches.is_present("storage-overlay") {
+            FileStorage::open_read_only(storage, 256)
+                .map(|base| BlockDevice::new(Box::new(OverlayStorage::new(Box::new(base)))))
+        } else {
+            BlockDevice::from_filename(storage, 256)
+        };
+        let block_device = match block_device {
+            Ok(block_device) => block_device,
+            Err(err) => {
+                eprintln!("unable to open storage {}: {}", storage, err);
+                process::exit(1);
+            }
+        };
+        let dma_block = Rc::new(RefCell::new(DmaBlock::new(block_device, ram.clone())));
         let borrowed_dma_block = Rc::clone(&dma_block);
         dma = Some(borrowed_dma_block);
         let borrowed_dma = Rc::clone(&dma_block);
-        memory_controller.map_shared(0x200a, 0x200d, borrowed_dma);
+        let dma_mapping = memory_controller.map_shared(0x200a, 0x2015, borrowed_dma);
+        memory_controller.set_name(dma_mapping, "dma");
+    }
+
+    let mut nvram: Option<Rc<RefCell<BatteryRam>>> = None;
+    if matches.is_present("nvram") {
+        let nvram_file = matches.value_of("nvram").unwrap();
+        let mut battery_ram = match BatteryRam::open(nvram_file, 2048) {
+            Ok(battery_ram) => battery_ram,
+            Err(err) => {
+                eprintln!("unable to open nvram {}: {}", nvram_file, err);
+                process::exit(1);
+            }
+        };
+        // written back once per second
+        battery_ram.flush_ticks = u64::from(hz);
+        let battery_ram = Rc::new(RefCell::new(battery_ram));
+        let nvram_mapping = memory_controller.map_shared(0x3000, 0x37ff, battery_ram.clone());
+        memory_controller.set_name(nvram_mapping, "nvram");
+        nvram = Some(battery_ram);
     }
 
-    let mut cpu = MOS6502::new(memory_controller);
+    // watchpoints see every access of the cpu
+    let watchpoints = Rc::new(RefCell::new(Watchpoints::new()));
+    let mut cpu = MOS6502::new(WatchedBus::new(memory_controller, watchpoints.clone()));
     cpu.pc = pc;
     cpu.debug = matches.is_present("debug");
+    cpu.symbols = symbol_table;
+
+    // the dma engines take the bus from the cpu and signal completion on IRQ
+    cpu.connect_bus_request(dma_memory.borrow().bus_request());
+    cpu.connect_irq(dma_memory.borrow().irq());
+    if let Some(block_device_dma) = dma.as_ref() {
+        cpu.connect_bus_request(block_device_dma.borrow().bus_request());
+        cpu.connect_irq(block_device_dma.borrow().irq());
+    }
 
-    let block_nmi = matches.is_present("no-vblank");
+    if cpu.debug {
+        for mapping in cpu.bus().bus().mappings() {
+            println!(
+                "${:04X}-${:04X} {} (wait states: {})",
+                mapping.start, mapping.end, mapping.name, mapping.wait_states
+            );
+        }
+    }
 
-    let mut last_ticks: u64 = 0;
+    let block_nmi = matches.is_present("no-vblank");
 
-    let ticks_per_frame = hz / vsync;
+    let ticks_per_frame = u64::from(hz / vsync);
 
     let mut in_debugger = false;
+    let mut stepper = Stepper::new();
 
     cpu.set_code_breakpoint(matches.is_present("code-breakpoint"));
 
-    loop {
-        let mut ticks_counter = i64::from(ticks_per_frame);
-        while ticks_counter > 0 {
-            if cpu.is_code_breakpoint_requested() || breakpoints.contains(&cpu.pc) {
+    let cpu = Rc::new(RefCell::new(cpu));
+
+    // the cpu runs at the master clock, the dma engines step along with it
+    let mut scheduler = Scheduler::new(u64::from(hz));
+    let cpu_device = scheduler.add_device(cpu.clone(), 1);
+    scheduler.add_device(dma_memory, 1);
+    if let Some(block_device_dma) = dma.as_ref() {
+        scheduler.add_device(block_device_dma.clone(), 1);
+    }
+    if let Some(battery_ram) = nvram.as_ref() {
+        scheduler.add_device(battery_ram.clone(), 1);
+    }
+    if let Some(shared_eeprom) = eeprom.as_ref() {
+        scheduler.add_device(shared_eeprom.clone(), 1);
+    }
+    scheduler.schedule_every(ticks_per_frame, ticks_per_frame, VBLANK);
+
+    let mut exit_code = loop {
+        if scheduler.peek() == Some(Slot::Device(cpu_device)) {
+            let mut cpu = cpu.borrow_mut();
+            if cpu.is_code_breakpoint_requested() {
                 in_debugger = true;
             }
+            // opcode lookups, breakpoint conditions and the debugger commands are not
+            // accesses of the program, the watchpoints do not see them
+            cpu.bus().suppress();
+            // stepping and breakpoints count instructions, not the cycles stolen by dma
+            if !cpu.is_stalled() {
+                if !in_debugger && !stepper.is_running() {
+                    in_debugger = stepper.check(&mut *cpu);
+                }
+                if !in_debugger {
+                    if let Some(id) = breakpoints.check(&mut *cpu) {
+                        println!("breakpoint #{}", id);
+                        in_debugger = true;
+                    }
+                }
+            }
             if in_debugger {
-                in_debugger = debugger(&mut cpu);
+                let mut watchpoints = watchpoints.borrow_mut();
+                let resume = debugger(&mut *cpu, &mut breakpoints, &mut watchpoints);
+                stepper.resume(resume, &mut *cpu);
+                in_debugger = false;
             }
+            cpu.bus().resume();
+        }
 
-            cpu.step();
-
-            if let Some(block_device_dma) = dma.as_mut() { block_device_dma.borrow_mut().step() }
-
-            if cpu.debug {
-                println!("[{:04X}] {}", cpu.debug_pc, cpu.debug_line);
+        match scheduler.step() {
+            Some(Slot::Device(device)) if device == cpu_device => {
+                let cpu = cpu.borrow();
+                if let Some(trap) = cpu.trap.as_ref() {
+                    eprintln!("[{:04X}] {}", cpu.debug_pc, trap);
+                    break 1;
+                }
+                if let Some(code) = exit_request.get() {
+                    break code;
+                }
+                // stop after the instruction touching a watched address
+                for hit in watchpoints.borrow_mut().take_hits() {
+                    println!("{}", watch_hit_str(&*cpu, &hit, cpu.debug_pc));
+                    in_debugger = true;
+                }
+                if check_uninitialized {
+                    for address in ram.borrow_mut().take_uninitialized_reads() {
+                        eprintln!(
+                            "[{:04X}] read of uninitialized ram at ${:04X}",
+                            cpu.debug_pc, address
+                        );
+                    }
+                }
+                if cpu.debug {
+                    println!("[{:04X}] {}", cpu.debug_pc, cpu.debug_line);
+                }
+            }
+            Some(Slot::Event(VBLANK)) => {
+                if aiv_framebuffer.borrow_mut().vblank() {
+                    break 0;
+                }
+                if stepper.frame() {
+                    in_debugger = true;
+                }
+                let mut cpu = cpu.borrow_mut();
+                // avoid NMI if the related vector is not in the rom, peeked so that a
+                // short rom does not trap and read watchpoints do not fire
+                let nmi_in_rom = cpu.bus().peek(0xfffb).is_some_and(|high| high >= 0xc0);
+                if !block_nmi && nmi_in_rom {
+                    cpu.raise(6);
+                }
+            }
+            // the dma engines read the ram on their own, their reads are not the cpu's
+            Some(Slot::Device(_)) if check_uninitialized => {
+                for address in ram.borrow_mut().take_uninitialized_reads() {
+                    eprintln!("[dma] read of uninitialized ram at ${:04X}", address);
+                }
             }
-            ticks_counter -= (cpu.ticks - last_ticks) as i64;
-            last_ticks = cpu.ticks;
+            _ => (),
+        }
+    };
+
+    if let Some(block_device_dma) = dma {
+        if let Err(err) = block_device_dma.borrow_mut().flush() {
+            eprintln!("unable to flush storage: {}", err);
+            exit_code = 1;
         }
-        if aiv_framebuffer.borrow_mut().vblank() {
-            break;
+    }
+    if let Some(battery_ram) = nvram {
+        if let Err(err) = battery_ram.borrow_mut().sync() {
+            eprintln!("unable to save nvram: {}", err);
+            exit_code = 1;
         }
-        // avoid NMI if the related vector is not in the rom
-        if !block_nmi && cpu.read(0xfffb) >= 0xc0 {
-            cpu.raise(6);
+    }
+    if let Some(shared_eeprom) = eeprom {
+        if let Err(err) = shared_eeprom.borrow_mut().save() {
+            eprintln!("unable to save eeprom: {}", err);
+            exit_code = 1;
         }
     }
+    exit_code
 }
@@ -10,6 +10,7 @@ use impostor::Clock;
 
 use std::env;
 use std::fs;
+use std::process;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -94,6 +95,10 @@ fn main() {
             _ => (),
         });
         chip8.step();
+        if let Some(trap) = chip8.trap.as_ref() {
+            eprintln!("[{:04X}] {}", chip8.pc, trap);
+            process::exit(1);
+        }
 
         if chip8.delay_timer > 0 {
             chip8.delay_timer -= 1;
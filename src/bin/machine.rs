@@ -0,0 +1,83 @@
+extern crate clap;
+extern crate impostor;
+
+use clap::{App, Arg};
+
+use impostor::machine::MachineBuilder;
+use impostor::symbols;
+
+use std::process;
+use std::thread;
+use std::time::Instant;
+
+fn main() {
+    let matches = App::new("machine")
+        .version("0.1")
+        .author("Roberto De Ioris <roberto@aiv01.it>")
+        .about("Run a machine from a description file")
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .help("report CPU state after each opcode"),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .takes_value(true)
+                .value_name("file")
+                .help("show the labels of a ca65 .dbg, VICE label or name = address file"),
+        )
+        .arg(Arg::with_name("description").index(1).required(true))
+        .get_matches();
+
+    let description = matches.value_of("description").unwrap();
+
+    let builder = match MachineBuilder::from_filename(description) {
+        Ok(builder) => builder,
+        Err(err) => panic!("{}", err),
+    };
+
+    let mut machine = match builder.build() {
+        Ok(machine) => machine,
+        Err(err) => panic!("{}", err),
+    };
+
+    machine.cpu.debug = matches.is_present("debug");
+
+    if let Some(symbols_file) = matches.value_of("symbols") {
+        machine.cpu.symbols = match symbols::load_file(symbols_file) {
+            Ok(symbol_table) => symbol_table,
+            Err(err) => panic!("{}", err),
+        };
+    }
+
+    if machine.cpu.debug {
+        for mapping in machine.cpu.bus().mappings() {
+            println!(
+                "${:04X}-${:04X} {} (wait states: {})",
+                mapping.start, mapping.end, mapping.name, mapping.wait_states
+            );
+        }
+    }
+
+    // the cpu runs at the hz of the description, checked every few steps
+    let start = Instant::now();
+    let mut steps: u64 = 0;
+    loop {
+        machine.step();
+        steps += 1;
+        if steps == 1000 {
+            steps = 0;
+            if let Some(ahead) = machine.emulated_time().checked_sub(start.elapsed()) {
+                thread::sleep(ahead);
+            }
+        }
+        if let Some(trap) = machine.cpu.trap.as_ref() {
+            eprintln!("[{:04X}] {}", machine.cpu.debug_pc, trap);
+            process::exit(1);
+        }
+        if machine.cpu.debug {
+            println!("[{:04X}] {}", machine.cpu.debug_pc, machine.cpu.debug_line);
+        }
+    }
+}
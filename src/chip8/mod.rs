@@ -1,5 +1,6 @@
+use offset_address;
 use rand;
-use {AddressBusIO, Clock};
+use {AddressBusExt, AddressBusIO, BusError, Clock, Debug, InstructionKind, RegisterInfo};
 
 pub struct Chip8<T: AddressBusIO<u16, u8>> {
     bus: T,
@@ -20,6 +21,9 @@ pub struct Chip8<T: AddressBusIO<u16, u8>> {
     pub sp: u8,
 
     pub redraw: bool,
+
+    // first failed bus access, the cpu halts until it is cleared
+    pub trap: Option<BusError>,
 }
 
 impl<T: AddressBusIO<u16, u8>> Chip8<T> {
@@ -35,38 +39,55 @@ impl<T: AddressBusIO<u16, u8>> Chip8<T> {
             screen: [0; 64 * 32],
             keys: [false; 16],
             redraw: false,
+            trap: None,
             bus: bus,
         }
     }
 
+    pub fn bus(&mut self) -> &mut T {
+        &mut self.bus
+    }
+
     fn read8(&mut self, addr: u16) -> u8 {
-        self.bus.read(addr)
+        match self.bus.try_read(addr) {
+            Ok(value) => value,
+            Err(err) => {
+                self.raise_trap(err);
+                0
+            }
+        }
     }
 
     fn write8(&mut self, addr: u16, value: u8) {
-        self.bus.write(addr, value)
+        if let Err(err) = self.bus.try_write(addr, value) {
+            self.raise_trap(err);
+        }
     }
 
-    fn read8_from_pc(&mut self) -> u8 {
-        let pc = self.advance_pc();
-        return self.read8(pc);
+    fn raise_trap(&mut self, err: BusError) {
+        if self.trap.is_none() {
+            self.trap = Some(err);
+        }
     }
 
     fn read16_from_pc(&mut self) -> u16 {
-        let high = u16::from(self.read8_from_pc());
-        let low = u16::from(self.read8_from_pc());
-        (high << 8) | low
-    }
-
-    fn advance_pc(&mut self) -> u16 {
         let pc = self.pc;
-        self.pc += 1;
-        return pc;
+        self.pc = offset_address(pc, 2);
+        match self.bus.try_read_u16_be(pc) {
+            Ok(value) => value,
+            Err(err) => {
+                self.raise_trap(err);
+                0
+            }
+        }
     }
 }
 
 impl<T: AddressBusIO<u16, u8>> Clock for Chip8<T> {
     fn step(&mut self) {
+        if self.trap.is_some() {
+            return;
+        }
         let opcode = self.read16_from_pc();
 
         let nnn = opcode & 0x0fff;
@@ -228,3 +249,99 @@ impl<T: AddressBusIO<u16, u8>> Clock for Chip8<T> {
         }
     }
 }
+
+const V_REGISTERS: [&str; 16] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+];
+
+impl<T: AddressBusIO<u16, u8>> Debug<u16, u8> for Chip8<T> {
+    fn address_str(&self, address: u16) -> String {
+        format!("${:03X}", address)
+    }
+
+    fn data_str(&self, data: u8) -> String {
+        format!("${:02X}", data)
+    }
+
+    // failed accesses read as 0 and do not trap the cpu
+    fn inspect(&mut self, address: u16) -> u8 {
+        self.bus.try_read(address).unwrap_or(0)
+    }
+
+    fn inject(&mut self, address: u16, data: u8) {
+        let _ = self.bus.try_write(address, data);
+    }
+
+    fn get_cursor(&self) -> u16 {
+        self.pc
+    }
+
+    fn set_cursor(&mut self, address: u16) {
+        self.pc = address;
+    }
+
+    fn next(&mut self) {
+        self.step();
+    }
+
+    fn set_code_breakpoint(&mut self, _enable: bool) {}
+
+    fn is_code_breakpoint_requested(&mut self) -> bool {
+        false
+    }
+
+    fn instruction_kind(&mut self) -> InstructionKind<u16> {
+        let pc = self.pc;
+        let opcode =
+            (u16::from(self.inspect(pc)) << 8) | u16::from(self.inspect(offset_address(pc, 1)));
+        match opcode {
+            0x00ee => InstructionKind::Return,
+            _ if opcode & 0xf000 == 0x2000 => InstructionKind::Call(offset_address(pc, 2)),
+            _ => InstructionKind::Other,
+        }
+    }
+
+    fn stack_pointer(&self) -> Option<u64> {
+        Some(u64::from(self.sp))
+    }
+
+    // V0-VF, then I, PC, SP and the timers
+    fn registers(&self) -> Vec<RegisterInfo> {
+        let mut registers: Vec<RegisterInfo> = V_REGISTERS
+            .iter()
+            .map(|name| RegisterInfo::new(name, 8))
+            .collect();
+        registers.push(RegisterInfo::new("I", 16));
+        registers.push(RegisterInfo::new("PC", 16));
+        registers.push(RegisterInfo::new("SP", 4));
+        registers.push(RegisterInfo::new("DT", 8));
+        registers.push(RegisterInfo::new("ST", 8));
+        registers
+    }
+
+    fn get_register(&self, index: usize) -> Option<u64> {
+        match index {
+            0..=15 => Some(u64::from(self.reg[index])),
+            16 => Some(u64::from(self.index)),
+            17 => Some(u64::from(self.pc)),
+            18 => Some(u64::from(self.sp)),
+            19 => Some(u64::from(self.delay_timer)),
+            20 => Some(u64::from(self.sound_timer)),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, index: usize, value: u64) -> bool {
+        match index {
+            0..=15 => self.reg[index] = value as u8,
+            16 => self.index = value as u16,
+            17 => self.pc = value as u16,
+            // the stack has 16 entries
+            18 if value < 16 => self.sp = value as u8,
+            19 => self.delay_timer = value as u8,
+            20 => self.sound_timer = value as u8,
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::num::ParseIntError;
+
+use {Address, Data, Debug};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+// two characters operators first, so <= is not taken for <
+const OPERATORS: [(&str, Operator); 6] = [
+    ("==", Operator::Equal),
+    ("!=", Operator::NotEqual),
+    ("<=", Operator::LessEqual),
+    (">=", Operator::GreaterEqual),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+// a [memory] byte, or a register, a number or a symbol
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Memory(String),
+    Value(String),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Operand, String> {
+        if text.is_empty() {
+            return Err("missing operand".to_string());
+        }
+        if text.starts_with('[') {
+            if !text.ends_with(']') || text.len() < 3 {
+                return Err(format!("invalid memory operand {}", text));
+            }
+            return Ok(Operand::Memory(text[1..text.len() - 1].to_string()));
+        }
+        Ok(Operand::Value(text.to_string()))
+    }
+
+    fn value<T: Address<FromStrRadixErr = ParseIntError>, U: Data, V: Debug<T, U>>(
+        &self,
+        debugged: &mut V,
+    ) -> Option<u64> {
+        match self {
+            Operand::Memory(address) => {
+                let address = super::parse_address(debugged, address).ok()?;
+                debugged.inspect(address).to_u64()
+            }
+            // registers win over symbols with the same name
+            Operand::Value(text) => match debugged.register(text) {
+                Some(value) => Some(value),
+                None => super::parse_address(debugged, text)
+                    .ok()
+                    .and_then(|value| value.to_u64()),
+            },
+        }
+    }
+}
+
+// compares two operands, like A==$FF or [$10]!=0
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    text: String,
+    left: Operand,
+    operator: Operator,
+    right: Operand,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let compact: String = text.split_whitespace().collect();
+        for (symbol, operator) in OPERATORS.iter() {
+            if let Some(position) = compact.find(symbol) {
+                return Ok(Condition {
+                    text: compact.clone(),
+                    left: Operand::parse(&compact[..position])?,
+                    operator: *operator,
+                    right: Operand::parse(&compact[position + symbol.len()..])?,
+                });
+            }
+        }
+        Err(format!("missing comparison in {}", text))
+    }
+
+    // unknown registers or symbols make the condition false
+    pub fn evaluate<T: Address<FromStrRadixErr = ParseIntError>, U: Data, V: Debug<T, U>>(
+        &self,
+        debugged: &mut V,
+    ) -> bool {
+        let (left, right) = match (self.left.value(debugged), self.right.value(debugged)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return false,
+        };
+        match self.operator {
+            Operator::Equal => left == right,
+            Operator::NotEqual => left != right,
+            Operator::Less => left < right,
+            Operator::LessEqual => left <= right,
+            Operator::Greater => left > right,
+            Operator::GreaterEqual => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+pub struct Breakpoint<T: Address> {
+    pub id: usize,
+    pub address: T,
+    pub enabled: bool,
+    // removed once it stops the execution
+    pub temporary: bool,
+    pub condition: Option<Condition>,
+    // the first hits do not stop the execution
+    pub ignore: u64,
+    // times the address was reached with the condition true
+    pub hits: u64,
+}
+
+pub struct Breakpoints<T: Address> {
+    breakpoints: Vec<Breakpoint<T>>,
+    next_id: usize,
+}
+
+impl<T: Address> Breakpoints<T> {
+    pub fn new() -> Breakpoints<T> {
+        Breakpoints {
+            breakpoints: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn add(&mut self, address: T, temporary: bool) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            address,
+            enabled: true,
+            temporary,
+            condition: None,
+            ignore: 0,
+            hits: 0,
+        });
+        id
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint<T>> {
+        self.breakpoints
+            .iter_mut()
+            .find(|breakpoint| breakpoint.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.breakpoints.len() != count
+    }
+
+    pub fn enable(&mut self, id: usize, enabled: bool) -> bool {
+        match self.get_mut(id) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint<T>> {
+        self.breakpoints.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    // to be called before executing the instruction at the cursor,
+    // returns the id of the breakpoint stopping the execution
+    pub fn check<U: Data, V: Debug<T, U>>(&mut self, debugged: &mut V) -> Option<usize>
+    where
+        T: Address<FromStrRadixErr = ParseIntError>,
+    {
+        let cursor = debugged.get_cursor();
+        let mut stopped = None;
+        for breakpoint in &mut self.breakpoints {
+            if !breakpoint.enabled || breakpoint.address != cursor {
+                continue;
+            }
+            if let Some(condition) = breakpoint.condition.as_ref() {
+                if !condition.evaluate(debugged) {
+                    continue;
+                }
+            }
+            breakpoint.hits += 1;
+            if breakpoint.hits > breakpoint.ignore && stopped.is_none() {
+                stopped = Some(breakpoint.id);
+            }
+        }
+        if let Some(id) = stopped {
+            self.breakpoints
+                .retain(|breakpoint| breakpoint.id != id || !breakpoint.temporary);
+        }
+        stopped
+    }
+}
+
+impl<T: Address> Default for Breakpoints<T> {
+    fn default() -> Self {
+        Breakpoints::new()
+    }
+}
@@ -1,29 +1,338 @@
 extern crate rustyline;
 use self::rustyline::Editor;
 
-use {Address, Data, Debug};
+use {Address, Data, Debug, InstructionKind, RegisterInfo};
 
 use std::num::ParseIntError;
 use utils::to_number;
 
+pub mod breakpoints;
+pub mod stepping;
+pub mod watchpoints;
+
+pub use self::breakpoints::{Breakpoint, Breakpoints, Condition};
+pub use self::stepping::{Resume, Stepper};
+pub use self::watchpoints::{WatchHit, WatchedBus, Watchpoint, Watchpoints};
+use trace::{Access, WatchKind};
+
+// numbers or symbols known to the debugged device
+fn parse_address<T: Address<FromStrRadixErr = ParseIntError>, U: Data, V: Debug<T, U>>(
+    debugged: &V,
+    text: &str,
+) -> Result<T, String> {
+    match to_number::<T>(text) {
+        Ok(address) => Ok(address),
+        Err(_) => match debugged.resolve(text) {
+            Some(address) => Ok(address),
+            None => Err(format!("unknown address {}", text)),
+        },
+    }
+}
+
+fn prompt<T: Address, U: Data, V: Debug<T, U>>(debugged: &V) -> String {
+    let cursor = debugged.get_cursor();
+    match debugged.symbol(cursor) {
+        Some(symbol) => format!("{} <{}>>>", debugged.address_str(cursor), symbol),
+        None => format!("{}>>", debugged.address_str(cursor)),
+    }
+}
+
+// b|tb <address> [if <condition>] [after <hits>]
+fn add_breakpoint<T: Address<FromStrRadixErr = ParseIntError>, U: Data, V: Debug<T, U>>(
+    debugged: &V,
+    breakpoints: &mut Breakpoints<T>,
+    arguments: &[&str],
+    temporary: bool,
+) -> Result<usize, String> {
+    let address = match arguments.first() {
+        Some(address) => parse_address(debugged, address)?,
+        None => return Err("missing address".to_string()),
+    };
+    let mut condition = None;
+    let mut ignore = 0;
+    let mut index = 1;
+    while index < arguments.len() {
+        match arguments[index] {
+            "if" => {
+                let end = arguments[index + 1..]
+                    .iter()
+                    .position(|argument| *argument == "after")
+                    .map_or(arguments.len(), |position| index + 1 + position);
+                condition = Some(Condition::parse(&arguments[index + 1..end].join(" "))?);
+                index = end;
+            }
+            "after" => match arguments.get(index + 1) {
+                Some(hits) => {
+                    ignore = match to_number::<u64>(hits) {
+                        Ok(hits) => hits,
+                        Err(err) => return Err(format!("invalid hits {}: {}", hits, err)),
+                    };
+                    index += 2;
+                }
+                None => return Err("missing hits".to_string()),
+            },
+            argument => return Err(format!("unexpected {}", argument)),
+        }
+    }
+    let id = breakpoints.add(address, temporary);
+    let breakpoint = breakpoints.get_mut(id).unwrap();
+    breakpoint.condition = condition;
+    breakpoint.ignore = ignore;
+    Ok(id)
+}
+
+fn list_breakpoints<T: Address, U: Data, V: Debug<T, U>>(
+    debugged: &V,
+    breakpoints: &Breakpoints<T>,
+) {
+    if breakpoints.is_empty() {
+        println!("no breakpoints");
+    }
+    for breakpoint in breakpoints.iter() {
+        let mut line = format!(
+            "#{} {}",
+            breakpoint.id,
+            debugged.address_str(breakpoint.address)
+        );
+        if let Some(symbol) = debugged.symbol(breakpoint.address) {
+            line += &format!(" <{}>", symbol);
+        }
+        line += if breakpoint.enabled {
+            " enabled"
+        } else {
+            " disabled"
+        };
+        line += &format!(" hits: {}", breakpoint.hits);
+        if let Some(condition) = breakpoint.condition.as_ref() {
+            line += &format!(" if {}", condition);
+        }
+        if breakpoint.ignore > 0 {
+            line += &format!(" after {}", breakpoint.ignore);
+        }
+        if breakpoint.temporary {
+            line += " temporary";
+        }
+        println!("{}", line);
+    }
+}
+
+// watch r|w|rw <address>[-<end>] [== value]
+fn add_watchpoint<
+    T: Address<FromStrRadixErr = ParseIntError>,
+    U: Data<FromStrRadixErr = ParseIntError>,
+    V: Debug<T, U>,
+>(
+    debugged: &V,
+    watchpoints: &mut Watchpoints<T, U>,
+    arguments: &[&str],
+) -> Result<usize, String> {
+    let kind = match arguments.first() {
+        Some(&"r") => WatchKind::Read,
+        Some(&"w") => WatchKind::Write,
+        Some(&"rw") => WatchKind::ReadWrite,
+        _ => return Err("expected r, w or rw".to_string()),
+    };
+    let (start, end) = match arguments.get(1) {
+        Some(range) => {
+            let mut parts = range.splitn(2, '-');
+            let start = parse_address(debugged, parts.next().unwrap())?;
+            match parts.next() {
+                Some(end) => (start, parse_address(debugged, end)?),
+                None => (start, start),
+            }
+        }
+        None => return Err("missing address".to_string()),
+    };
+    if end < start {
+        return Err("end before start".to_string());
+    }
+    let filter: String = arguments[2..].concat();
+    let value = if filter.is_empty() {
+        None
+    } else if let Some(value) = filter.strip_prefix("==") {
+        match to_number::<U>(value) {
+            Ok(value) => Some(value),
+            Err(err) => return Err(format!("invalid value {}: {}", value, err)),
+        }
+    } else {
+        return Err(format!("unexpected {}", filter));
+    };
+    Ok(watchpoints.add(start, end, kind, value))
+}
+
+fn list_watchpoints<T: Address, U: Data, V: Debug<T, U>>(
+    debugged: &V,
+    watchpoints: &Watchpoints<T, U>,
+) {
+    if watchpoints.is_empty() {
+        println!("no watchpoints");
+    }
+    for watchpoint in watchpoints.iter() {
+        let kind = match watchpoint.kind {
+            WatchKind::Read => "r",
+            WatchKind::Write => "w",
+            WatchKind::ReadWrite => "rw",
+        };
+        let mut line = format!(
+            "#{} {} {}",
+            watchpoint.id,
+            kind,
+            debugged.address_str(watchpoint.start)
+        );
+        if watchpoint.end != watchpoint.start {
+            line += &format!("-{}", debugged.address_str(watchpoint.end));
+        }
+        if let Some(value) = watchpoint.value {
+            line += &format!(" == {}", debugged.data_str(value));
+        }
+        println!("{}", line);
+    }
+}
+
+// describes a watchpoint hit, pc is the address of the accessing instruction
+pub fn watch_hit_str<T: Address, U: Data, V: Debug<T, U>>(
+    debugged: &V,
+    hit: &WatchHit<T, U>,
+    pc: T,
+) -> String {
+    let access = match hit.access {
+        Access::Read => "read",
+        Access::Write => "write",
+    };
+    let value = match hit.old_value {
+        Some(old_value) => format!(
+            "{} -> {}",
+            debugged.data_str(old_value),
+            debugged.data_str(hit.value)
+        ),
+        None => debugged.data_str(hit.value),
+    };
+    format!(
+        "watchpoint #{}: {} {} by {}: {}",
+        hit.id,
+        access,
+        debugged.address_str(hit.address),
+        debugged.address_str(pc),
+        value
+    )
+}
+
+// NAME=$VALUE pairs, digits follow the register width
+fn register_str(register: &RegisterInfo, value: u64) -> String {
+    let digits = register.bits.div_ceil(4) as usize;
+    format!("{}=${:02$X}", register.name, value, digits)
+}
+
+// all the registers, or the named ones
+pub fn registers_str<T: Address, U: Data, V: Debug<T, U>>(
+    debugged: &V,
+    names: &[&str],
+) -> Result<String, String> {
+    let registers = debugged.registers();
+    if registers.is_empty() {
+        return Err("no registers".to_string());
+    }
+    let indexes = if names.is_empty() {
+        (0..registers.len()).collect()
+    } else {
+        let mut indexes = Vec::new();
+        for name in names {
+            match debugged.register_index(name) {
+                Some(index) => indexes.push(index),
+                None => return Err(format!("unknown register {}", name)),
+            }
+        }
+        indexes
+    };
+    let pairs: Vec<String> = indexes
+        .into_iter()
+        .filter_map(|index| {
+            debugged
+                .get_register(index)
+                .map(|value| register_str(&registers[index], value))
+        })
+        .collect();
+    Ok(pairs.join(" "))
+}
+
+// values wider than the register are refused
+pub fn set_register<T: Address, U: Data, V: Debug<T, U>>(
+    debugged: &mut V,
+    name: &str,
+    text: &str,
+) -> Result<(), String> {
+    let index = match debugged.register_index(name) {
+        Some(index) => index,
+        None => return Err(format!("unknown register {}", name)),
+    };
+    let register = debugged.registers()[index];
+    let value = match to_number::<u64>(text) {
+        Ok(value) => value,
+        Err(err) => return Err(format!("invalid value {}: {}", text, err)),
+    };
+    if value & !register.mask() != 0 {
+        return Err(format!(
+            "{} does not fit in the {} bits of {}",
+            text, register.bits, register.name
+        ));
+    }
+    if !debugged.set_register(index, value) {
+        return Err(format!("unable to set {}", register.name));
+    }
+    Ok(())
+}
+
+// an optional positive count, 1 when missing
+fn count(argument: Option<&str>) -> Result<u64, String> {
+    match argument {
+        Some(text) => match to_number::<u64>(text) {
+            Ok(0) => Err("the count must be at least 1".to_string()),
+            Ok(count) => Ok(count),
+            Err(err) => Err(format!("invalid count {}: {}", text, err)),
+        },
+        None => Ok(1),
+    }
+}
+
+// steps over calls, other instructions are single stepped
+pub fn step_over<T: Address, U: Data, V: Debug<T, U>>(debugged: &mut V) -> Resume<T> {
+    match debugged.instruction_kind() {
+        InstructionKind::Call(address) => Resume::Until(address),
+        _ => Resume::Step(1),
+    }
+}
+
+// the id of a breakpoint or a watchpoint
+fn breakpoint_id(argument: Option<&str>) -> Result<usize, String> {
+    match argument {
+        Some(id) => match to_number::<usize>(id.trim_start_matches('#')) {
+            Ok(id) => Ok(id),
+            Err(err) => Err(format!("invalid id {}: {}", id, err)),
+        },
+        None => Err("missing id".to_string()),
+    }
+}
+
+// returns how the execution goes on, to be passed to Stepper::resume
 pub fn debugger<
     T: Address<FromStrRadixErr = ParseIntError>,
     U: Data<FromStrRadixErr = ParseIntError>,
     V: Debug<T, U>,
 >(
     debugged: &mut V,
-) -> bool {
+    breakpoints: &mut Breakpoints<T>,
+    watchpoints: &mut Watchpoints<T, U>,
+) -> Resume<T> {
     let mut rl = Editor::<()>::new();
     loop {
-        let readline =
-            rl.readline(format!("{}>>", debugged.address_str(debugged.get_cursor())).as_ref());
+        let readline = rl.readline(prompt(debugged).as_ref());
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
                 let mut iter = line.split_whitespace();
                 match iter.next() {
                     Some("p") => match iter.next() {
-                        Some(value) => match to_number::<T>(value) {
+                        Some(value) => match parse_address(debugged, value) {
                             Ok(address) => match iter.next() {
                                 Some(amount) => match to_number::<T>(amount) {
                                     Ok(qt) => {
@@ -57,7 +366,7 @@ pub fn debugger<
                         _ => println!("syntax: p <address>"),
                     },
                     Some("w") => match iter.next() {
-                        Some(value) => match to_number::<T>(value) {
+                        Some(value) => match parse_address(debugged, value) {
                             Ok(address) => match iter.next() {
                                 Some(argument) => match to_number::<U>(argument) {
                                     Ok(data) => {
@@ -72,7 +381,7 @@ pub fn debugger<
                         _ => println!("syntax: w <address> <value>"),
                     },
                     Some("j") => match iter.next() {
-                        Some(value) => match to_number::<T>(value) {
+                        Some(value) => match parse_address(debugged, value) {
                             Ok(address) => {
                                 debugged.set_cursor(address);
                             }
@@ -80,17 +389,108 @@ pub fn debugger<
                         },
                         _ => println!("syntax: j <address>"),
                     },
-                    Some("q") => return false,
-                    Some("r") => return false,
-                    Some("s") => return true,
+                    Some(command @ "b") | Some(command @ "tb") => {
+                        let arguments: Vec<&str> = iter.collect();
+                        match add_breakpoint(debugged, breakpoints, &arguments, command == "tb") {
+                            Ok(id) => println!("breakpoint #{}", id),
+                            Err(err) => {
+                                println!("Error: {}", err);
+                                println!(
+                                    "syntax: {} <address> [if <condition>] [after <hits>]",
+                                    command
+                                );
+                            }
+                        }
+                    }
+                    Some("bl") => list_breakpoints(debugged, breakpoints),
+                    Some(command @ "bc") | Some(command @ "bd") | Some(command @ "be") => {
+                        match breakpoint_id(iter.next()) {
+                            Ok(id) => {
+                                let found = match command {
+                                    "bc" => breakpoints.remove(id),
+                                    "bd" => breakpoints.enable(id, false),
+                                    _ => breakpoints.enable(id, true),
+                                };
+                                if !found {
+                                    println!("no breakpoint #{}", id);
+                                }
+                            }
+                            Err(err) => println!("Error: {}", err),
+                        }
+                    }
+                    Some("watch") => {
+                        let arguments: Vec<&str> = iter.collect();
+                        if arguments.is_empty() {
+                            list_watchpoints(debugged, watchpoints);
+                        } else {
+                            match add_watchpoint(debugged, watchpoints, &arguments) {
+                                Ok(id) => println!("watchpoint #{}", id),
+                                Err(err) => {
+                                    println!("Error: {}", err);
+                                    println!("syntax: watch r|w|rw <address>[-<end>] [== value]");
+                                }
+                            }
+                        }
+                    }
+                    Some("unwatch") => match breakpoint_id(iter.next()) {
+                        Ok(id) => {
+                            if !watchpoints.remove(id) {
+                                println!("no watchpoint #{}", id);
+                            }
+                        }
+                        Err(err) => println!("Error: {}", err),
+                    },
+                    // r is an alias of regs, c resumes
+                    Some("r") | Some("regs") => {
+                        let names: Vec<&str> = iter.collect();
+                        match registers_str(debugged, &names) {
+                            Ok(registers) => println!("{}", registers),
+                            Err(err) => {
+                                println!("Error: {}", err);
+                                println!("syntax: r|regs [<register>...]");
+                            }
+                        }
+                    }
+                    Some("set") => match (iter.next(), iter.next()) {
+                        (Some(name), Some(value)) => {
+                            if let Err(err) = set_register(debugged, name, value) {
+                                println!("Error: {}", err);
+                            }
+                        }
+                        _ => println!("syntax: set <register> <value>"),
+                    },
+                    Some("q") => return Resume::Run,
+                    Some("s") => match count(iter.next()) {
+                        Ok(count) => return Resume::Step(count),
+                        Err(err) => println!("Error: {}", err),
+                    },
+                    Some("n") | Some("next") => return step_over(debugged),
+                    Some("finish") => return Resume::Finish,
+                    Some("until") => match iter.next() {
+                        Some(value) => match parse_address(debugged, value) {
+                            Ok(address) => return Resume::Until(address),
+                            Err(err) => println!("Error: {}", err),
+                        },
+                        None => println!("syntax: until <address>"),
+                    },
+                    Some("c") => match iter.next() {
+                        Some(frames) => match count(Some(frames)) {
+                            Ok(count) => return Resume::Frames(count),
+                            Err(err) => println!("Error: {}", err),
+                        },
+                        None => return Resume::Run,
+                    },
                     Some(command) => println!("unknown command {}", command),
                     None => (),
                 }
             }
             Err(err) => {
                 println!("Error: {}", err);
-                return false;
+                return Resume::Run;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,110 @@
+use {Address, Data, Debug, InstructionKind};
+
+// how the execution goes on when leaving the debugger
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Resume<T: Address> {
+    // until the next breakpoint
+    Run,
+    // the given number of instructions
+    Step(u64),
+    // until the cursor reaches the address
+    Until(T),
+    // until the current subroutine returns
+    Finish,
+    // the given number of frames
+    Frames(u64),
+}
+
+// decides when to get back to the debugger, breakpoints are checked separately
+pub struct Stepper<T: Address> {
+    resume: Resume<T>,
+    // the stack pointer when finish was requested, returning above it leaves the subroutine
+    frame: Option<u64>,
+    // calls entered and not returned yet while finishing, when there is no stack pointer
+    depth: u64,
+    // the kind of the instruction being executed while finishing
+    pending: InstructionKind<T>,
+}
+
+impl<T: Address> Stepper<T> {
+    pub fn new() -> Stepper<T> {
+        Stepper {
+            resume: Resume::Run,
+            frame: None,
+            depth: 0,
+            pending: InstructionKind::Other,
+        }
+    }
+
+    // to be called with the cursor on the instruction about to be executed
+    pub fn resume<U: Data, V: Debug<T, U>>(&mut self, resume: Resume<T>, debugged: &mut V) {
+        self.resume = resume;
+        self.frame = debugged.stack_pointer();
+        self.depth = 0;
+        self.pending = InstructionKind::Other;
+        self.record(debugged);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.resume == Resume::Run
+    }
+
+    fn record<U: Data, V: Debug<T, U>>(&mut self, debugged: &mut V) {
+        if self.resume == Resume::Finish {
+            self.pending = debugged.instruction_kind();
+        }
+    }
+
+    // to be called before executing the instruction at the cursor,
+    // returns true when the debugger has to be entered
+    pub fn check<U: Data, V: Debug<T, U>>(&mut self, debugged: &mut V) -> bool {
+        let stop = match self.resume {
+            Resume::Run | Resume::Frames(_) => false,
+            Resume::Step(ref mut count) => {
+                *count = count.saturating_sub(1);
+                *count == 0
+            }
+            Resume::Until(address) => debugged.get_cursor() == address,
+            // interrupts push and pop frames without a call, only the stack pointer tells
+            Resume::Finish if self.frame.is_some() => {
+                self.pending == InstructionKind::Return && debugged.stack_pointer() > self.frame
+            }
+            Resume::Finish => match self.pending {
+                InstructionKind::Call(_) => {
+                    self.depth += 1;
+                    false
+                }
+                InstructionKind::Return if self.depth == 0 => true,
+                InstructionKind::Return => {
+                    self.depth -= 1;
+                    false
+                }
+                InstructionKind::Other => false,
+            },
+        };
+        if stop {
+            self.resume = Resume::Run;
+        } else {
+            self.record(debugged);
+        }
+        stop
+    }
+
+    // to be called at the end of every frame, returns true when the debugger has to be entered
+    pub fn frame(&mut self) -> bool {
+        if let Resume::Frames(ref mut count) = self.resume {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.resume = Resume::Run;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<T: Address> Default for Stepper<T> {
+    fn default() -> Self {
+        Stepper::new()
+    }
+}
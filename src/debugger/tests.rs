@@ -0,0 +1,324 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use chip8::Chip8;
+use debugger::{
+    registers_str, set_register, step_over, watch_hit_str, Breakpoints, Condition, Resume, Stepper,
+    WatchedBus, Watchpoints,
+};
+use memcontroller::MemoryControllerShared;
+use mos6502::MOS6502;
+use ram::Ram;
+use random::Random;
+use trace::{Access, WatchKind};
+use {AddressBusIO, Clock, Debug, InstructionKind, Interrupt, Line};
+
+fn cpu_at(pc: u16) -> MOS6502<Ram<u8>> {
+    let mut cpu = MOS6502::new(Ram::new(1024));
+    cpu.pc = pc;
+    cpu
+}
+
+#[test]
+fn breakpoint_hits_and_ignore() {
+    let mut breakpoints = Breakpoints::new();
+    let mut cpu = cpu_at(0x10);
+    let id = breakpoints.add(0x10, false);
+    breakpoints.get_mut(id).unwrap().ignore = 1;
+    assert_eq!(breakpoints.check(&mut cpu), None);
+    assert_eq!(breakpoints.check(&mut cpu), Some(id));
+    assert_eq!(breakpoints.iter().next().unwrap().hits, 2);
+
+    breakpoints.enable(id, false);
+    assert_eq!(breakpoints.check(&mut cpu), None);
+    assert!(breakpoints.remove(id));
+    assert!(!breakpoints.remove(id));
+}
+
+#[test]
+fn breakpoint_skips_stalled_cycles() {
+    let mut breakpoints = Breakpoints::new();
+    let mut cpu = cpu_at(0x10);
+    let bus_request = Line::new();
+    cpu.connect_bus_request(bus_request.clone());
+    let id = breakpoints.add(0x10, false);
+    bus_request.set(true);
+    // checked only when the cpu is about to fetch, like the machine loops do
+    for _ in 0..3 {
+        if !cpu.is_stalled() {
+            breakpoints.check(&mut cpu);
+        }
+        cpu.step();
+    }
+    assert_eq!(cpu.pc, 0x10);
+    assert_eq!(breakpoints.iter().next().unwrap().hits, 0);
+    bus_request.set(false);
+    assert!(!cpu.is_stalled());
+    assert_eq!(breakpoints.check(&mut cpu), Some(id));
+    assert_eq!(breakpoints.iter().next().unwrap().hits, 1);
+}
+
+#[test]
+fn temporary_breakpoint() {
+    let mut breakpoints = Breakpoints::new();
+    let mut cpu = cpu_at(0x10);
+    let id = breakpoints.add(0x10, true);
+    assert_eq!(breakpoints.check(&mut cpu), Some(id));
+    assert!(breakpoints.is_empty());
+}
+
+#[test]
+fn conditional_breakpoint() {
+    let mut breakpoints = Breakpoints::new();
+    let mut cpu = cpu_at(0x10);
+    cpu.symbols.insert("counter", 0x80);
+    let on_a = breakpoints.add(0x10, false);
+    breakpoints.get_mut(on_a).unwrap().condition = Some(Condition::parse("a == $ff").unwrap());
+    let on_memory = breakpoints.add(0x10, false);
+    breakpoints.get_mut(on_memory).unwrap().condition =
+        Some(Condition::parse("[counter]>=2").unwrap());
+
+    assert_eq!(breakpoints.check(&mut cpu), None);
+    cpu.a = 0xff;
+    assert_eq!(breakpoints.check(&mut cpu), Some(on_a));
+    cpu.a = 0;
+    cpu.inject(0x80, 2);
+    assert_eq!(breakpoints.check(&mut cpu), Some(on_memory));
+    assert_eq!(breakpoints.iter().next().unwrap().hits, 1);
+
+    assert!(Condition::parse("A").is_err());
+    assert!(Condition::parse("==1").is_err());
+}
+
+#[test]
+fn watchpoints_report_old_and_new_values() {
+    let watchpoints = Rc::new(RefCell::new(Watchpoints::new()));
+    let mut ram = Ram::new(1024);
+    // LDA #$FF, STA $0100, LDA $10
+    ram.fill(vec![0xa9, 0xff, 0x8d, 0x00, 0x01, 0xa5, 0x10], 0);
+    ram.fill(vec![0x17], 0x100);
+    let mut cpu = MOS6502::new(WatchedBus::new(ram, watchpoints.clone()));
+    let on_write = watchpoints
+        .borrow_mut()
+        .add(0x100, 0x1ff, WatchKind::Write, Some(0xff));
+    let on_read = watchpoints
+        .borrow_mut()
+        .add(0x10, 0x10, WatchKind::Read, None);
+
+    cpu.step();
+    assert!(watchpoints.borrow_mut().take_hits().is_empty());
+    cpu.step();
+    let hits = watchpoints.borrow_mut().take_hits();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, on_write);
+    assert_eq!(hits[0].address, 0x100);
+    assert_eq!(hits[0].access, Access::Write);
+    assert_eq!(hits[0].old_value, Some(0x17));
+    assert_eq!(hits[0].value, 0xff);
+    assert_eq!(
+        watch_hit_str(&cpu, &hits[0], cpu.debug_pc),
+        "watchpoint #1: write $0100 by $0002: $17 -> $FF"
+    );
+
+    cpu.step();
+    let hits = watchpoints.borrow_mut().take_hits();
+    assert_eq!(hits[0].id, on_read);
+    assert_eq!(hits[0].old_value, None);
+
+    // the accesses of the host are not reported while suppressed
+    cpu.bus().suppress();
+    cpu.inspect(0x10);
+    cpu.bus().resume();
+    assert!(watchpoints.borrow_mut().take_hits().is_empty());
+    cpu.inspect(0x10);
+    assert_eq!(watchpoints.borrow_mut().take_hits().len(), 1);
+}
+
+#[test]
+fn watched_writes_peek_the_old_value() {
+    let watchpoints = Rc::new(RefCell::new(Watchpoints::new()));
+    let mut ram: Ram<u8> = Ram::new(256);
+    ram.track_uninitialized(true);
+    let ram = Rc::new(RefCell::new(ram));
+    let mut bus = MemoryControllerShared::new();
+    bus.map(0x0000, 0x00ff, ram.clone());
+    bus.map(0x0100, 0x0100, Rc::new(RefCell::new(Random::new())));
+    let mut bus = WatchedBus::new(bus, watchpoints.clone());
+    watchpoints
+        .borrow_mut()
+        .add(0x0000, 0x0100, WatchKind::Write, None);
+
+    bus.write(0x0010u16, 0x01);
+    bus.write(0x0100u16, 0x02);
+    let hits = watchpoints.borrow_mut().take_hits();
+    assert_eq!(hits[0].old_value, Some(0x00));
+    // the random generator can not be read without side effects
+    assert_eq!(hits[1].old_value, None);
+    assert!(ram.borrow_mut().take_uninitialized_reads().is_empty());
+}
+
+#[test]
+fn mos6502_registers() {
+    let mut cpu = cpu_at(0x0200);
+    cpu.a = 0x10;
+    assert_eq!(
+        registers_str(&cpu, &[]).unwrap(),
+        "A=$10 X=$00 Y=$00 SP=$FF P=$24 PC=$0200"
+    );
+    assert_eq!(registers_str(&cpu, &["pc", "a"]).unwrap(), "PC=$0200 A=$10");
+    assert!(registers_str(&cpu, &["V0"]).is_err());
+
+    set_register(&mut cpu, "x", "$42").unwrap();
+    assert_eq!(cpu.x, 0x42);
+    set_register(&mut cpu, "PC", "$C000").unwrap();
+    assert_eq!(cpu.pc, 0xc000);
+    assert!(set_register(&mut cpu, "A", "$100").is_err());
+    assert_eq!(cpu.register("y"), Some(0));
+    set_register(&mut cpu, "status", "$A5").unwrap();
+    assert_eq!(cpu.register("P"), Some(0xa5));
+}
+
+#[test]
+fn chip8_registers() {
+    let mut cpu = Chip8::new(Ram::new(4096));
+    set_register(&mut cpu, "VF", "1").unwrap();
+    set_register(&mut cpu, "I", "$0300").unwrap();
+    assert_eq!(cpu.reg[15], 1);
+    assert_eq!(cpu.index, 0x300);
+    assert_eq!(
+        registers_str(&cpu, &["vf", "i", "pc"]).unwrap(),
+        "VF=$01 I=$0300 PC=$0200"
+    );
+    assert_eq!(cpu.registers().len(), 21);
+    // the stack pointer indexes 16 entries
+    set_register(&mut cpu, "SP", "$F").unwrap();
+    assert_eq!(registers_str(&cpu, &["sp"]).unwrap(), "SP=$F");
+    assert!(set_register(&mut cpu, "SP", "$10").is_err());
+    assert!(!cpu.set_register(18, 0x10));
+    assert_eq!(cpu.sp, 0x0f);
+}
+
+// JSR $0010, LDA #$01, BRK at $0000; INX, JSR $0020, RTS at $0010; INY, RTS at $0020
+fn calling_program() -> MOS6502<Ram<u8>> {
+    let mut cpu = cpu_at(0x0000);
+    cpu.bus()
+        .fill(vec![0x20, 0x10, 0x00, 0xa9, 0x01, 0x00], 0x0000);
+    cpu.bus().fill(vec![0xe8, 0x20, 0x20, 0x00, 0x60], 0x0010);
+    cpu.bus().fill(vec![0xc8, 0x60], 0x0020);
+    cpu
+}
+
+// runs until the stepper stops, returns the executed instructions
+fn run(stepper: &mut Stepper<u16>, cpu: &mut MOS6502<Ram<u8>>, resume: Resume<u16>) -> usize {
+    stepper.resume(resume, cpu);
+    let mut executed = 0;
+    loop {
+        cpu.step();
+        executed += 1;
+        if stepper.check(cpu) {
+            return executed;
+        }
+        assert!(executed < 100);
+    }
+}
+
+#[test]
+fn instruction_kinds() {
+    let mut cpu = calling_program();
+    assert_eq!(cpu.instruction_kind(), InstructionKind::Call(0x0003));
+    cpu.pc = 0x0014;
+    assert_eq!(cpu.instruction_kind(), InstructionKind::Return);
+    cpu.pc = 0x0010;
+    assert_eq!(cpu.instruction_kind(), InstructionKind::Other);
+
+    let mut chip8 = Chip8::new(Ram::new(4096));
+    chip8.bus().fill(vec![0x22, 0x06, 0x00, 0xee], 0x0200);
+    assert_eq!(chip8.instruction_kind(), InstructionKind::Call(0x0202));
+    chip8.pc = 0x0202;
+    assert_eq!(chip8.instruction_kind(), InstructionKind::Return);
+}
+
+#[test]
+fn step_count_and_step_over() {
+    let mut stepper = Stepper::new();
+    let mut cpu = calling_program();
+    assert_eq!(run(&mut stepper, &mut cpu, Resume::Step(2)), 2);
+    assert_eq!(cpu.pc, 0x0011);
+
+    let mut cpu = calling_program();
+    let resume = step_over(&mut cpu);
+    assert_eq!(resume, Resume::Until(0x0003));
+    assert_eq!(run(&mut stepper, &mut cpu, resume), 6);
+    assert_eq!(cpu.y, 1);
+    assert_eq!(step_over(&mut cpu), Resume::Step(1));
+}
+
+#[test]
+fn step_count_skips_stalled_cycles() {
+    let mut stepper = Stepper::new();
+    let mut cpu = calling_program();
+    let bus_request = Line::new();
+    cpu.connect_bus_request(bus_request.clone());
+    stepper.resume(Resume::Step(2), &mut cpu);
+    // the dma takes the bus after the first instruction, the checks skip its cycles
+    let mut cycles = 0;
+    loop {
+        cpu.step();
+        cycles += 1;
+        bus_request.set(cycles < 4);
+        if !cpu.is_stalled() && stepper.check(&mut cpu) {
+            break;
+        }
+        assert!(cycles < 10);
+    }
+    assert_eq!(cycles, 5);
+    assert_eq!(cpu.pc, 0x0011);
+}
+
+#[test]
+fn finish_skips_nested_calls() {
+    let mut stepper = Stepper::new();
+    let mut cpu = calling_program();
+    cpu.step();
+    assert_eq!(run(&mut stepper, &mut cpu, Resume::Finish), 5);
+    assert_eq!(cpu.pc, 0x0003);
+    assert!(stepper.is_running());
+}
+
+#[test]
+fn finish_ignores_interrupt_returns() {
+    let mut stepper = Stepper::new();
+    let mut cpu = MOS6502::new(Ram::new(0x10000));
+    cpu.bus()
+        .fill(vec![0x20, 0x10, 0x00, 0xa9, 0x01, 0x00], 0x0000);
+    cpu.bus().fill(vec![0xe8, 0x20, 0x20, 0x00, 0x60], 0x0010);
+    cpu.bus().fill(vec![0xc8, 0x60], 0x0020);
+    // NOP, RTI as the NMI handler
+    cpu.bus().fill(vec![0xea, 0x40], 0x0030);
+    cpu.bus().fill(vec![0x30, 0x00], 0xfffa);
+    cpu.step();
+    stepper.resume(Resume::Finish, &mut cpu);
+    cpu.raise(6);
+    let mut executed = 0;
+    loop {
+        cpu.step();
+        executed += 1;
+        if stepper.check(&mut cpu) {
+            break;
+        }
+        assert!(executed < 100);
+    }
+    // the handler, then INX, JSR, INY, RTS, RTS
+    assert_eq!(executed, 7);
+    assert_eq!(cpu.pc, 0x0003);
+}
+
+#[test]
+fn frames() {
+    let mut stepper = Stepper::new();
+    let mut cpu = calling_program();
+    stepper.resume(Resume::Frames(2), &mut cpu);
+    assert!(!stepper.frame());
+    assert!(stepper.frame());
+    assert!(!stepper.frame());
+}
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use trace::{Access, WatchKind};
+use {Address, AddressBusIO, BusError, Data};
+
+pub struct Watchpoint<T: Address, U: Data> {
+    pub id: usize,
+    pub start: T,
+    pub end: T,
+    pub kind: WatchKind,
+    // only accesses of this value trigger the watchpoint
+    pub value: Option<U>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WatchHit<T: Address, U: Data> {
+    pub id: usize,
+    pub address: T,
+    pub access: Access,
+    // the content before a write
+    pub old_value: Option<U>,
+    pub value: U,
+}
+
+pub struct Watchpoints<T: Address, U: Data> {
+    watchpoints: Vec<Watchpoint<T, U>>,
+    hits: Vec<WatchHit<T, U>>,
+    next_id: usize,
+}
+
+impl<T: Address, U: Data> Watchpoints<T, U> {
+    pub fn new() -> Watchpoints<T, U> {
+        Watchpoints {
+            watchpoints: Vec::new(),
+            hits: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn add(&mut self, start: T, end: T, kind: WatchKind, value: Option<U>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint {
+            id,
+            start,
+            end,
+            kind,
+            value,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != count
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint<T, U>> {
+        self.watchpoints.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    // whether an access to the address can trigger a watchpoint
+    pub fn is_watched(&self, address: T, access: Access) -> bool {
+        self.watchpoints.iter().any(|watchpoint| {
+            address >= watchpoint.start
+                && address <= watchpoint.end
+                && watchpoint.kind.matches(access)
+        })
+    }
+
+    // called by the bus for every access
+    pub fn notify(&mut self, address: T, access: Access, old_value: Option<U>, value: U) {
+        for watchpoint in &self.watchpoints {
+            if address >= watchpoint.start
+                && address <= watchpoint.end
+                && watchpoint.kind.matches(access)
+                && (watchpoint.value.is_none() || watchpoint.value == Some(value))
+            {
+                self.hits.push(WatchHit {
+                    id: watchpoint.id,
+                    address,
+                    access,
+                    old_value,
+                    value,
+                });
+            }
+        }
+    }
+
+    // the hits since the last call, to be checked after every instruction
+    pub fn take_hits(&mut self) -> Vec<WatchHit<T, U>> {
+        self.hits.split_off(0)
+    }
+}
+
+impl<T: Address, U: Data> Default for Watchpoints<T, U> {
+    fn default() -> Self {
+        Watchpoints::new()
+    }
+}
+
+// reports the accesses of a cpu to the watchpoints, the ones made on behalf of the
+// host (like the debugger and the stepper) go between suppress() and resume()
+pub struct WatchedBus<T: Address, U: Data, B: AddressBusIO<T, U>> {
+    bus: B,
+    watchpoints: Rc<RefCell<Watchpoints<T, U>>>,
+    suppressed: bool,
+}
+
+impl<T: Address, U: Data, B: AddressBusIO<T, U>> WatchedBus<T, U, B> {
+    pub fn new(bus: B, watchpoints: Rc<RefCell<Watchpoints<T, U>>>) -> WatchedBus<T, U, B> {
+        WatchedBus {
+            bus,
+            watchpoints,
+            suppressed: false,
+        }
+    }
+
+    pub fn suppress(&mut self) {
+        self.suppressed = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.suppressed = false;
+    }
+
+    pub fn bus(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+}
+
+impl<T: Address, U: Data, B: AddressBusIO<T, U>> AddressBusIO<T, U> for WatchedBus<T, U, B> {
+    fn read(&mut self, address: T) -> U {
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn write(&mut self, address: T, value: U) {
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
+        }
+    }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let value = self.bus.try_read(address)?;
+        if !self.suppressed {
+            self.watchpoints
+                .borrow_mut()
+                .notify(address, Access::Read, None, value);
+        }
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        if self.suppressed {
+            return self.bus.try_write(address, value);
+        }
+        let mut watchpoints = self.watchpoints.borrow_mut();
+        // devices that can not be peeked (like i/o registers) report no old value
+        let old_value = if watchpoints.is_watched(address, Access::Write) {
+            self.bus.peek(address)
+        } else {
+            None
+        };
+        self.bus.try_write(address, value)?;
+        watchpoints.notify(address, Access::Write, old_value, value);
+        Ok(())
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        self.bus.take_wait_states()
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        self.bus.peek(address)
+    }
+}
@@ -1,17 +1,78 @@
 use std::cell::RefCell;
+use std::cmp;
+use std::io;
+use std::mem;
 use std::rc::Rc;
 use storage::BlockDevice;
-use {Address, AddressBusBlockIO, AddressBusIO, As, Clock};
+use {
+    offset_address, Address, AddressBusBlockIO, AddressBusExt, AddressBusIO, As, BusError, Clock,
+    Line,
+};
+
+// the registers of both engines are little endian and advance while running, bytes
+// past the address width are ignored; count, control and status are shared:
+// DmaBlock: $00-$03 block, $04-$07 address
+// DmaMemory: $00-$03 source, $04-$07 destination, $0C source stride,
+// $0D destination stride, $0E fill value
+const REGISTER_BLOCK: usize = 0x00;
+const REGISTER_ADDRESS: usize = 0x04;
+const REGISTER_SOURCE: usize = 0x00;
+const REGISTER_DESTINATION: usize = 0x04;
+const REGISTER_COUNT: usize = 0x08;
+const REGISTER_CONTROL: usize = 0x0a;
+const REGISTER_STATUS: usize = 0x0b;
+const REGISTER_SOURCE_STRIDE: usize = 0x0c;
+const REGISTER_DESTINATION_STRIDE: usize = 0x0d;
+const REGISTER_FILL_VALUE: usize = 0x0e;
+
+// control: bit 0 selects bus to block (DmaBlock) or fill mode (DmaMemory),
+// bit 1 raises an IRQ on completion, writing bit 6 flushes the block device,
+// writing bit 7 starts the transfer
+const BUS_TO_BLOCK: u8 = 0x01;
+const FILL: u8 = 0x01;
+const IRQ_ENABLE: u8 = 0x02;
+const BLOCK_FLUSH: u8 = 0x40;
+const START: u8 = 0x80;
+// status: bit 7 while busy, bit 6 once done (cleared by reading it),
+// bit 5 when the transfer failed (until the next start)
+const ERROR: u8 = 0x20;
+const DONE: u8 = 0x40;
+const BUSY: u8 = 0x80;
+
+fn get_byte<T: Address>(register: T, index: usize) -> u8 {
+    if index >= mem::size_of::<T>() {
+        return 0;
+    }
+    ((register >> (index * 8)) & T::from(0xff).unwrap())
+        .to_u8()
+        .unwrap()
+}
+
+fn set_byte<T: Address>(register: T, index: usize, value: u8) -> T {
+    if index >= mem::size_of::<T>() {
+        return register;
+    }
+    let shift = index * 8;
+    (register & !(T::from(0xff).unwrap() << shift)) | (T::from(value).unwrap() << shift)
+}
 
 pub struct DmaBlock<T: Address> {
     block_device: BlockDevice,
     bus: Rc<RefCell<dyn AddressBusIO<T, u8>>>,
     block: T,
-    blocks_to_transfer: u8,
+    // a count of 0 transfers 65536 blocks, like the memory dma does with bytes
+    blocks_to_transfer: u16,
     address: T,
-    flags: u8,
-    block_counter: u8,
-    address_counter: u8,
+    control: u8,
+    running: bool,
+    // bytes moved on every step, the cpu is kept off the bus meanwhile
+    pub bytes_per_cycle: usize,
+    cache_block: Vec<u8>,
+    offset: usize,
+    done: bool,
+    error: Option<io::Error>,
+    bus_request: Line,
+    irq: Line,
 }
 
 impl<T: Address> DmaBlock<T> {
@@ -19,87 +80,357 @@ impl<T: Address> DmaBlock<T> {
         block_device: BlockDevice,
         bus: Rc<RefCell<dyn AddressBusIO<T, u8>>>,
     ) -> DmaBlock<T> {
+        let block_size = block_device.block_size;
         DmaBlock {
             block_device,
             bus,
             block: T::zero(),
             blocks_to_transfer: 0,
             address: T::zero(),
-            flags: 0,
-            block_counter: 0,
-            address_counter: 0,
+            control: 0,
+            running: false,
+            bytes_per_cycle: 1,
+            cache_block: vec![0; block_size],
+            offset: 0,
+            done: false,
+            error: None,
+            bus_request: Line::new(),
+            irq: Line::new(),
         }
     }
+
+    // to be connected to the RDY input of the cpu
+    pub fn bus_request(&self) -> Line {
+        self.bus_request.clone()
+    }
+
+    pub fn irq(&self) -> Line {
+        self.irq.clone()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.running
+    }
+
+    // writes back the cached blocks of the device
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.block_device.flush()
+    }
+
+    // the reason of the last failed transfer
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn start(&mut self) {
+        self.running = true;
+        self.offset = 0;
+        self.done = false;
+        self.error = None;
+        self.irq.set(false);
+    }
+
+    fn finish(&mut self) {
+        self.running = false;
+        self.bus_request.set(false);
+        self.done = true;
+        if self.control & IRQ_ENABLE != 0 {
+            self.irq.set(true);
+        }
+    }
+
+    fn fail(&mut self, err: io::Error) {
+        self.error = Some(err);
+        self.finish();
+    }
+
+    fn status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.is_busy() {
+            status |= BUSY;
+        }
+        if self.done {
+            status |= DONE;
+            self.done = false;
+            self.irq.set(false);
+        }
+        if self.error.is_some() {
+            status |= ERROR;
+        }
+        status
+    }
 }
 
 impl<T: Address + As<usize>> Clock for DmaBlock<T> {
     fn step(&mut self) {
-        if self.blocks_to_transfer == 0 {
+        if !self.running {
             return;
         }
 
-        let current_block = self.block;
-        let address = self.address;
+        let block_size = self.block_device.block_size;
 
-        let mut cache_block = vec![0; self.block_device.block_size];
-
-        if self.flags & 0x01 == 1 {
-            // bus to block
-            for i in 0..self.block_device.block_size {
-                cache_block[i] = self.bus.borrow_mut().read(address + T::from(i).unwrap());
+        if self.offset == 0 {
+            self.bus_request.set(true);
+            if self.control & BUS_TO_BLOCK == 0 {
+                if let Err(err) = self.block_device.read(self.block, &mut self.cache_block) {
+                    self.fail(err);
+                    return;
+                }
             }
-            self.block_device.write(current_block, &cache_block);
+        }
+
+        let end = cmp::min(self.offset + cmp::max(self.bytes_per_cycle, 1), block_size);
+        let address = offset_address(self.address, self.offset);
+
+        if self.control & BUS_TO_BLOCK != 0 {
+            self.bus
+                .borrow_mut()
+                .read_block(address, &mut self.cache_block[self.offset..end]);
         } else {
-            // block to bus
-            self.block_device.read(current_block, &mut cache_block);
-            for i in 0..self.block_device.block_size {
-                self.bus
-                    .borrow_mut()
-                    .write(address + T::from(i).unwrap(), cache_block[i]);
+            self.bus
+                .borrow_mut()
+                .write_block(address, &self.cache_block[self.offset..end]);
+        }
+
+        self.offset = end;
+        if self.offset < block_size {
+            return;
+        }
+
+        if self.control & BUS_TO_BLOCK != 0 {
+            if let Err(err) = self.block_device.write(self.block, &self.cache_block) {
+                self.fail(err);
+                return;
             }
         }
 
-        self.blocks_to_transfer -= 1;
+        self.offset = 0;
+        self.blocks_to_transfer = self.blocks_to_transfer.wrapping_sub(1);
         self.block += T::one();
-        self.address += T::from(self.block_device.block_size).unwrap();
+        self.address = offset_address(self.address, block_size);
+
+        if self.blocks_to_transfer == 0 {
+            self.finish();
+        }
     }
 }
 
-impl AddressBusIO<u16, u8> for DmaBlock<u16> {
-    fn write(&mut self, address: u16, value: u8) {
-        match address {
-            0 => {
-                self.block |= u16::from(value) << (8 * (1 - self.block_counter));
-                self.block_counter += 1;
-                if self.block_counter > 1 {
-                    self.block_counter = 0;
-                }
+// the register file can be mapped on a bus of any width
+impl<T: Address + As<usize>, U: Address> AddressBusIO<T, u8> for DmaBlock<U> {
+    fn write(&mut self, address: T, value: u8) {
+        match address.as_() {
+            register @ REGISTER_BLOCK..=0x03 => {
+                self.block = set_byte(self.block, register - REGISTER_BLOCK, value)
+            }
+            register @ REGISTER_ADDRESS..=0x07 => {
+                self.address = set_byte(self.address, register - REGISTER_ADDRESS, value)
+            }
+            register @ REGISTER_COUNT..=0x09 => {
+                self.blocks_to_transfer =
+                    set_byte(self.blocks_to_transfer, register - REGISTER_COUNT, value)
             }
-            1 => {
-                self.address |= u16::from(value) << (8 * (1 - self.address_counter));
-                self.address_counter += 1;
-                if self.address_counter > 1 {
-                    self.address_counter = 0;
+            REGISTER_CONTROL => {
+                self.control = value & !(BLOCK_FLUSH | START);
+                if value & BLOCK_FLUSH != 0 {
+                    if let Err(err) = self.flush() {
+                        self.error = Some(err);
+                    }
                 }
+                if value & START != 0 {
+                    self.start();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read(&mut self, address: T) -> u8 {
+        match address.as_() {
+            register @ REGISTER_BLOCK..=0x03 => get_byte(self.block, register - REGISTER_BLOCK),
+            register @ REGISTER_ADDRESS..=0x07 => {
+                get_byte(self.address, register - REGISTER_ADDRESS)
             }
-            2 => self.blocks_to_transfer = value,
-            3 => {
-                self.flags = value;
-                self.block = 0;
-                self.blocks_to_transfer = 0;
-                self.address = 0;
-                self.block_counter = 0;
-                self.address_counter = 0;
+            register @ REGISTER_COUNT..=0x09 => {
+                get_byte(self.blocks_to_transfer, register - REGISTER_COUNT)
             }
+            REGISTER_CONTROL => self.control,
+            REGISTER_STATUS => self.status(),
+            _ => 0,
+        }
+    }
+}
+
+// copies between bus addresses, or fills them with a constant
+pub struct DmaMemory<T: Address> {
+    bus: Rc<RefCell<dyn AddressBusIO<T, u8>>>,
+    source: T,
+    destination: T,
+    // a count of 0 transfers 65536 bytes
+    count: u16,
+    remaining: u32,
+    // added to the addresses after every byte, 0 keeps hitting the same port
+    source_stride: u8,
+    destination_stride: u8,
+    fill_value: u8,
+    control: u8,
+    pub bytes_per_cycle: usize,
+    done: bool,
+    error: Option<BusError>,
+    bus_request: Line,
+    irq: Line,
+}
+
+impl<T: Address> DmaMemory<T> {
+    pub fn new(bus: Rc<RefCell<dyn AddressBusIO<T, u8>>>) -> DmaMemory<T> {
+        DmaMemory {
+            bus,
+            source: T::zero(),
+            destination: T::zero(),
+            count: 0,
+            remaining: 0,
+            source_stride: 1,
+            destination_stride: 1,
+            fill_value: 0,
+            control: 0,
+            bytes_per_cycle: 1,
+            done: false,
+            error: None,
+            bus_request: Line::new(),
+            irq: Line::new(),
+        }
+    }
+
+    pub fn bus_request(&self) -> Line {
+        self.bus_request.clone()
+    }
+
+    pub fn irq(&self) -> Line {
+        self.irq.clone()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.remaining > 0
+    }
+
+    // the access that stopped the last transfer
+    pub fn error(&self) -> Option<&BusError> {
+        self.error.as_ref()
+    }
+
+    fn start(&mut self) {
+        self.remaining = if self.count == 0 {
+            0x10000
+        } else {
+            u32::from(self.count)
+        };
+        self.done = false;
+        self.error = None;
+        self.irq.set(false);
+    }
+
+    fn finish(&mut self) {
+        self.remaining = 0;
+        self.bus_request.set(false);
+        self.done = true;
+        if self.control & IRQ_ENABLE != 0 {
+            self.irq.set(true);
+        }
+    }
+
+    fn transfer(&mut self) -> Result<(), BusError> {
+        let value = if self.control & FILL != 0 {
+            self.fill_value
+        } else {
+            self.bus.borrow_mut().try_read(self.source)?
+        };
+        self.bus.borrow_mut().try_write(self.destination, value)
+    }
+
+    fn status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.is_busy() {
+            status |= BUSY;
+        }
+        if self.done {
+            status |= DONE;
+            self.done = false;
+            self.irq.set(false);
+        }
+        if self.error.is_some() {
+            status |= ERROR;
+        }
+        status
+    }
+}
+
+impl<T: Address> Clock for DmaMemory<T> {
+    fn step(&mut self) {
+        if self.remaining == 0 {
+            return;
+        }
+
+        self.bus_request.set(true);
+        for _ in 0..cmp::max(self.bytes_per_cycle, 1) {
+            // a faulting access ends the transfer, the registers point at it
+            if let Err(err) = self.transfer() {
+                self.error = Some(err);
+                self.finish();
+                return;
+            }
+            self.source = offset_address(self.source, self.source_stride as usize);
+            self.destination = offset_address(self.destination, self.destination_stride as usize);
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.finish();
+                return;
+            }
+        }
+    }
+}
+
+// the same register model as DmaBlock, for a bus of any width
+impl<T: Address + As<usize>, U: Address> AddressBusIO<T, u8> for DmaMemory<U> {
+    fn write(&mut self, address: T, value: u8) {
+        match address.as_() {
+            register @ REGISTER_SOURCE..=0x03 => {
+                self.source = set_byte(self.source, register - REGISTER_SOURCE, value)
+            }
+            register @ REGISTER_DESTINATION..=0x07 => {
+                self.destination =
+                    set_byte(self.destination, register - REGISTER_DESTINATION, value)
+            }
+            register @ REGISTER_COUNT..=0x09 => {
+                self.count = set_byte(self.count, register - REGISTER_COUNT, value)
+            }
+            REGISTER_CONTROL => {
+                self.control = value & !START;
+                if value & START != 0 {
+                    self.start();
+                }
+            }
+            REGISTER_SOURCE_STRIDE => self.source_stride = value,
+            REGISTER_DESTINATION_STRIDE => self.destination_stride = value,
+            REGISTER_FILL_VALUE => self.fill_value = value,
             _ => (),
         }
     }
 
-    fn read(&mut self, address: u16) -> u8 {
-        match address {
-            2 => self.blocks_to_transfer,
-            3 => self.flags,
+    fn read(&mut self, address: T) -> u8 {
+        match address.as_() {
+            register @ REGISTER_SOURCE..=0x03 => get_byte(self.source, register - REGISTER_SOURCE),
+            register @ REGISTER_DESTINATION..=0x07 => {
+                get_byte(self.destination, register - REGISTER_DESTINATION)
+            }
+            register @ REGISTER_COUNT..=0x09 => get_byte(self.count, register - REGISTER_COUNT),
+            REGISTER_CONTROL => self.control,
+            REGISTER_STATUS => self.status(),
+            REGISTER_SOURCE_STRIDE => self.source_stride,
+            REGISTER_DESTINATION_STRIDE => self.destination_stride,
+            REGISTER_FILL_VALUE => self.fill_value,
             _ => 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,220 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use dma::{DmaBlock, DmaMemory};
+use mos6502::MOS6502;
+use ram::Ram;
+use storage::{BlockDevice, MemoryStorage};
+use {AddressBusIO, Clock};
+
+fn block_device() -> BlockDevice {
+    let mut data = vec![0; 48];
+    for (index, value) in data.iter_mut().enumerate() {
+        *value = index as u8;
+    }
+    BlockDevice::new(Box::new(MemoryStorage::from_vec(data, 16)))
+}
+
+#[test]
+fn block_to_bus_steals_cycles() {
+    let ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    let mut dma: DmaBlock<u16> = DmaBlock::new(block_device(), ram.clone());
+    dma.bytes_per_cycle = 4;
+    let bus_request = dma.bus_request();
+    let irq = dma.irq();
+
+    // block 0 to $0020, irq on completion
+    dma.write(0x04u16, 0x20);
+    dma.write(0x05u16, 0x00);
+    dma.write(0x08u16, 1);
+    dma.write(0x0au16, 0x82);
+    assert_eq!(dma.read(0x0bu16), 0x80);
+
+    for _ in 0..3 {
+        dma.step();
+        assert!(bus_request.is_set());
+    }
+    dma.step();
+    assert!(!bus_request.is_set());
+    assert!(irq.is_set());
+
+    assert_eq!(ram.borrow_mut().read(0x20u16), 0);
+    assert_eq!(ram.borrow_mut().read(0x2fu16), 15);
+
+    // reading the status acknowledges the interrupt
+    assert_eq!(dma.read(0x0bu16), 0x40);
+    assert!(!irq.is_set());
+    assert_eq!(dma.read(0x0bu16), 0x00);
+    // the registers advanced with the transfer
+    assert_eq!(dma.read(0x00u16), 1);
+    assert_eq!(dma.read(0x04u16), 0x30);
+    assert_eq!(dma.read(0x08u16), 0);
+    assert_eq!(dma.read(0x0au16), 0x02);
+}
+
+#[test]
+fn registers_follow_the_address_width() {
+    let ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    let mut dma: DmaBlock<u32> = DmaBlock::new(block_device(), ram.clone());
+    for (register, value) in [0x11u8, 0x22, 0x33, 0x44].iter().enumerate() {
+        dma.write(0x04u32 + register as u32, *value);
+    }
+    dma.write(0x09u32, 0x01);
+    assert_eq!(dma.read(0x07u32), 0x44);
+    assert_eq!(dma.read(0x04u32), 0x11);
+    assert_eq!(dma.read(0x09u32), 0x01);
+
+    let mut memory: DmaMemory<u32> = DmaMemory::new(ram.clone());
+    memory.write(0x07u32, 0x44);
+    memory.write(0x04u32, 0x11);
+    assert_eq!(memory.read(0x07u32), 0x44);
+    assert_eq!(memory.read(0x04u32), 0x11);
+
+    let mut narrow: DmaBlock<u16> = DmaBlock::new(block_device(), ram);
+    narrow.write(0x06u8, 0x33);
+    narrow.write(0x05u8, 0x22);
+    assert_eq!(narrow.read(0x06u8), 0);
+    assert_eq!(narrow.read(0x05u8), 0x22);
+}
+
+#[test]
+fn cpu_waits_for_the_bus() {
+    let mut ram = Ram::new(1024);
+    ram.fill(vec![0xe8], 0);
+    let dma_ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    let dma: DmaBlock<u16> = DmaBlock::new(block_device(), dma_ram);
+    let mut cpu = MOS6502::new(ram);
+    cpu.connect_bus_request(dma.bus_request());
+
+    dma.bus_request().set(true);
+    cpu.step();
+    assert_eq!(cpu.x, 0);
+    assert_eq!(cpu.ticks, 1);
+
+    dma.bus_request().set(false);
+    cpu.step();
+    assert_eq!(cpu.x, 1);
+}
+
+fn memory_dma(ram: &Rc<RefCell<Ram<u8>>>) -> DmaMemory<u16> {
+    let mut dma = DmaMemory::new(ram.clone());
+    dma.bytes_per_cycle = 2;
+    dma
+}
+
+#[test]
+fn memory_copy() {
+    let ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    ram.borrow_mut().fill(vec![1, 2, 3, 4], 0x10);
+    let mut dma = memory_dma(&ram);
+    let bus_request = dma.bus_request();
+    // $0010 -> $0080, 4 bytes
+    dma.write(0x00u16, 0x10);
+    dma.write(0x04u16, 0x80);
+    dma.write(0x08u16, 4);
+    dma.write(0x0au16, 0x80);
+    dma.step();
+    assert!(bus_request.is_set());
+    assert_eq!(dma.read(0x0bu16), 0x80);
+    dma.step();
+    assert!(!bus_request.is_set());
+    assert_eq!(dma.read(0x0bu16), 0x40);
+    assert_eq!(dma.read(0x00u16), 0x14);
+    let mut buffer = [0; 4];
+    for (index, value) in buffer.iter_mut().enumerate() {
+        *value = ram.borrow_mut().read(0x80 + index as u16);
+    }
+    assert_eq!(buffer, [1, 2, 3, 4]);
+}
+
+#[test]
+fn memory_fill_and_strides() {
+    let ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    ram.borrow_mut().fill(vec![1, 0, 2, 0, 3], 0x10);
+    let mut dma = memory_dma(&ram);
+    let irq = dma.irq();
+
+    // fill $0040-$0047 with $aa
+    dma.write(0x04u16, 0x40);
+    dma.write(0x08u16, 8);
+    dma.write(0x0eu16, 0xaa);
+    dma.write(0x0au16, 0x81);
+    for _ in 0..4 {
+        dma.step();
+    }
+    assert_eq!(ram.borrow_mut().read(0x47u16), 0xaa);
+    assert_eq!(ram.borrow_mut().read(0x48u16), 0x00);
+
+    // gather every other byte into a single port, with an irq at the end
+    dma.write(0x00u16, 0x10);
+    dma.write(0x04u16, 0x90);
+    dma.write(0x08u16, 3);
+    dma.write(0x0cu16, 2);
+    dma.write(0x0du16, 0);
+    dma.write(0x0au16, 0x82);
+    dma.step();
+    dma.step();
+    assert!(irq.is_set());
+    assert_eq!(ram.borrow_mut().read(0x90u16), 3);
+    assert_eq!(ram.borrow_mut().read(0x91u16), 0);
+    assert_eq!(dma.read(0x0au16), 0x02);
+    assert_eq!(dma.read(0x0bu16), 0x40);
+    assert!(!irq.is_set());
+}
+
+#[test]
+fn memory_bus_errors_stop_the_transfer() {
+    let ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    let mut dma = memory_dma(&ram);
+    let irq = dma.irq();
+    let bus_request = dma.bus_request();
+    // the source runs past the end of the ram
+    dma.write(0x00u16, 0xfe);
+    dma.write(0x04u16, 0x10);
+    dma.write(0x08u16, 4);
+    dma.write(0x0au16, 0x82);
+    dma.step();
+    dma.step();
+    assert!(!bus_request.is_set());
+    assert!(irq.is_set());
+    assert!(dma.error().is_some());
+    assert_eq!(dma.read(0x00u16), 0x00);
+    assert_eq!(dma.read(0x01u16), 0x01);
+    // done and error
+    assert_eq!(dma.read(0x0bu16), 0x60);
+}
+
+#[test]
+fn block_count_zero_is_the_maximum() {
+    let ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    let mut dma: DmaBlock<u16> = DmaBlock::new(block_device(), ram);
+    dma.bytes_per_cycle = 16;
+    // the count is left at 0, the 3 blocks image runs out before the end
+    dma.write(0x0au16, 0x82);
+    assert_eq!(dma.read(0x0bu16), 0x80);
+    for _ in 0..3 {
+        dma.step();
+    }
+    assert!(dma.is_busy());
+    assert_eq!(dma.read(0x08u16), 0xfd);
+    assert_eq!(dma.read(0x09u16), 0xff);
+    dma.step();
+    assert!(!dma.is_busy());
+    assert!(dma.irq().is_set());
+    assert_eq!(dma.read(0x0bu16), 0x60);
+}
+
+#[test]
+fn block_device_errors_stop_the_transfer() {
+    let ram: Rc<RefCell<Ram<u8>>> = Rc::new(RefCell::new(Ram::new(256)));
+    let mut dma: DmaBlock<u16> = DmaBlock::new(block_device(), ram);
+    let bus_request = dma.bus_request();
+    // block 3 is past the end of the 3 blocks image
+    dma.write(0x00u16, 3);
+    dma.write(0x08u16, 1);
+    dma.write(0x0au16, 0x80);
+    dma.step();
+    assert!(!bus_request.is_set());
+    assert!(dma.error().is_some());
+    assert_eq!(dma.read(0x0bu16), 0x60);
+}
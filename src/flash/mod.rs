@@ -0,0 +1,274 @@
+use std::io;
+use std::path::Path;
+
+use persistent::PersistentImage;
+use {Address, AddressBusIO, As, Clock};
+
+// the status returned by reads while the chip is busy:
+// bit 7 is the complement of the value being written, bit 6 toggles on every read
+fn polling_status(value: u8, toggle: &mut bool) -> u8 {
+    *toggle = !*toggle;
+    (!value & 0x80) | if *toggle { 0x40 } else { 0x00 }
+}
+
+// 32KiB parallel eeprom, timings are in ticks (the defaults assume a 1MHz clock)
+pub struct At28c256 {
+    image: PersistentImage,
+    // a page write is committed once no byte is loaded for this long
+    pub byte_load_ticks: u64,
+    pub write_cycle_ticks: u64,
+    page: Option<usize>,
+    loading: u64,
+    busy: u64,
+    last_value: u8,
+    toggle: bool,
+}
+
+const AT28C256_SIZE: usize = 32768;
+const AT28C256_PAGE_SIZE: usize = 64;
+
+impl At28c256 {
+    pub fn new() -> At28c256 {
+        At28c256 {
+            image: PersistentImage::new(AT28C256_SIZE, 0xff),
+            byte_load_ticks: 150,
+            write_cycle_ticks: 10000,
+            page: None,
+            loading: 0,
+            busy: 0,
+            last_value: 0,
+            toggle: false,
+        }
+    }
+
+    // loads the content from the file (when it exists), it is written back by save() and on drop
+    pub fn persist<P: AsRef<Path>>(&mut self, filename: P) -> io::Result<()> {
+        self.image.persist(filename.as_ref())
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        self.image.save()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.image.cells
+    }
+
+    // programmers can bypass the write timing
+    pub fn fill(&mut self, data: &[u8], offset: usize) {
+        for (index, value) in data.iter().enumerate() {
+            if offset + index >= AT28C256_SIZE {
+                break;
+            }
+            self.image.set(offset + index, *value);
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.loading > 0 || self.busy > 0
+    }
+}
+
+impl Default for At28c256 {
+    fn default() -> Self {
+        At28c256::new()
+    }
+}
+
+impl Clock for At28c256 {
+    fn step(&mut self) {
+        if self.loading > 0 {
+            self.loading -= 1;
+            if self.loading == 0 {
+                self.page = None;
+                self.busy = self.write_cycle_ticks;
+            }
+        } else if self.busy > 0 {
+            self.busy -= 1;
+        }
+    }
+}
+
+impl<T: Address + As<usize>> AddressBusIO<T, u8> for At28c256 {
+    fn read(&mut self, address: T) -> u8 {
+        if self.is_busy() {
+            return polling_status(self.last_value, &mut self.toggle);
+        }
+        self.image.cells[address.as_() % AT28C256_SIZE]
+    }
+
+    fn write(&mut self, address: T, value: u8) {
+        if self.busy > 0 {
+            return;
+        }
+        let index = address.as_() % AT28C256_SIZE;
+        let page = index / AT28C256_PAGE_SIZE;
+        // the page is latched by the first byte, the others must fall in it
+        if *self.page.get_or_insert(page) != page {
+            return;
+        }
+        self.image.set(index, value);
+        self.last_value = value;
+        self.loading = self.byte_load_ticks.max(1);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Command {
+    Read,
+    Unlock1,
+    Unlock2,
+    Program,
+    EraseSetup,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+// 128KiB flash with JEDEC command sequences, timings are in ticks (defaults assume 1MHz)
+pub struct Sst39sf010 {
+    image: PersistentImage,
+    pub program_ticks: u64,
+    pub sector_erase_ticks: u64,
+    pub chip_erase_ticks: u64,
+    command: Command,
+    software_id: bool,
+    busy: u64,
+    last_value: u8,
+    toggle: bool,
+}
+
+const SST39SF010_SIZE: usize = 131072;
+const SST39SF010_SECTOR_SIZE: usize = 4096;
+const SST_MANUFACTURER_ID: u8 = 0xbf;
+const SST39SF010_DEVICE_ID: u8 = 0xb5;
+
+impl Sst39sf010 {
+    pub fn new() -> Sst39sf010 {
+        Sst39sf010 {
+            image: PersistentImage::new(SST39SF010_SIZE, 0xff),
+            program_ticks: 20,
+            sector_erase_ticks: 25000,
+            chip_erase_ticks: 100000,
+            command: Command::Read,
+            software_id: false,
+            busy: 0,
+            last_value: 0,
+            toggle: false,
+        }
+    }
+
+    // loads the content from the file (when it exists), it is written back by save() and on drop
+    pub fn persist<P: AsRef<Path>>(&mut self, filename: P) -> io::Result<()> {
+        self.image.persist(filename.as_ref())
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        self.image.save()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.image.cells
+    }
+
+    // programmers can bypass the command sequences
+    pub fn fill(&mut self, data: &[u8], offset: usize) {
+        for (index, value) in data.iter().enumerate() {
+            if offset + index >= SST39SF010_SIZE {
+                break;
+            }
+            self.image.set(offset + index, *value);
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy > 0
+    }
+
+    fn start(&mut self, ticks: u64, value: u8) {
+        self.busy = ticks.max(1);
+        self.last_value = value;
+    }
+}
+
+impl Default for Sst39sf010 {
+    fn default() -> Self {
+        Sst39sf010::new()
+    }
+}
+
+impl Clock for Sst39sf010 {
+    fn step(&mut self) {
+        if self.busy > 0 {
+            self.busy -= 1;
+        }
+    }
+}
+
+impl<T: Address + As<usize>> AddressBusIO<T, u8> for Sst39sf010 {
+    fn read(&mut self, address: T) -> u8 {
+        if self.is_busy() {
+            return polling_status(self.last_value, &mut self.toggle);
+        }
+        let index = address.as_() % SST39SF010_SIZE;
+        if self.software_id {
+            return if index & 0x01 == 0 {
+                SST_MANUFACTURER_ID
+            } else {
+                SST39SF010_DEVICE_ID
+            };
+        }
+        self.image.cells[index]
+    }
+
+    fn write(&mut self, address: T, value: u8) {
+        if self.is_busy() {
+            return;
+        }
+        let index = address.as_() % SST39SF010_SIZE;
+        // only A14-A0 are decoded for the command cycles
+        let command_address = index & 0x7fff;
+        self.command = match (self.command, command_address, value) {
+            (Command::Program, _, _) => {
+                // programming can only clear bits
+                let programmed = self.image.cells[index] & value;
+                self.image.set(index, programmed);
+                let ticks = self.program_ticks;
+                self.start(ticks, value);
+                Command::Read
+            }
+            // exits the software id mode from any other state
+            (_, _, 0xf0) => {
+                self.software_id = false;
+                Command::Read
+            }
+            (Command::Read, 0x5555, 0xaa) => Command::Unlock1,
+            (Command::Unlock1, 0x2aaa, 0x55) => Command::Unlock2,
+            (Command::Unlock2, 0x5555, 0xa0) => Command::Program,
+            (Command::Unlock2, 0x5555, 0x80) => Command::EraseSetup,
+            (Command::Unlock2, 0x5555, 0x90) => {
+                self.software_id = true;
+                Command::Read
+            }
+            (Command::EraseSetup, 0x5555, 0xaa) => Command::EraseUnlock1,
+            (Command::EraseUnlock1, 0x2aaa, 0x55) => Command::EraseUnlock2,
+            (Command::EraseUnlock2, _, 0x30) => {
+                let sector = index - index % SST39SF010_SECTOR_SIZE;
+                self.image.fill(sector, SST39SF010_SECTOR_SIZE, 0xff);
+                let ticks = self.sector_erase_ticks;
+                self.start(ticks, 0xff);
+                Command::Read
+            }
+            (Command::EraseUnlock2, 0x5555, 0x10) => {
+                self.image.fill(0, SST39SF010_SIZE, 0xff);
+                let ticks = self.chip_erase_ticks;
+                self.start(ticks, 0xff);
+                Command::Read
+            }
+            // a wrong cycle aborts the sequence
+            _ => Command::Read,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests;
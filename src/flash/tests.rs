@@ -0,0 +1,105 @@
+use std::fs;
+
+use flash::{At28c256, Sst39sf010};
+use utils::temp_path;
+use {AddressBusIO, Clock};
+
+fn wait<T: Clock>(chip: &mut T, ticks: u64) {
+    for _ in 0..ticks {
+        chip.step();
+    }
+}
+
+fn sst_command(flash: &mut Sst39sf010, command: u8) {
+    flash.write(0x5555u32, 0xaa);
+    flash.write(0x2aaau32, 0x55);
+    flash.write(0x5555u32, command);
+}
+
+#[test]
+fn at28c256_page_write_data_polling() {
+    let mut eeprom = At28c256::new();
+    eeprom.write(0x1040u16, 0x12);
+    eeprom.write(0x1041u16, 0x85);
+    // outside of the latched page
+    eeprom.write(0x1080u16, 0x99);
+    assert!(eeprom.is_busy());
+
+    // the complement of bit 7 of the last byte, with bit 6 toggling
+    assert_eq!(eeprom.read(0x1041u16), 0x40);
+    assert_eq!(eeprom.read(0x1041u16), 0x00);
+
+    wait(&mut eeprom, 150);
+    // writes are ignored during the write cycle
+    eeprom.write(0x0000u16, 0x00);
+    wait(&mut eeprom, 10000);
+    assert!(!eeprom.is_busy());
+    assert_eq!(eeprom.read(0x1040u16), 0x12);
+    assert_eq!(eeprom.read(0x1041u16), 0x85);
+    assert_eq!(eeprom.read(0x1080u16), 0xff);
+    assert_eq!(eeprom.read(0x0000u16), 0xff);
+}
+
+#[test]
+fn at28c256_persists() {
+    let path = temp_path("at28c256");
+    {
+        let mut eeprom = At28c256::new();
+        eeprom.persist(&path).unwrap();
+        eeprom.write(0x7fffu16, 0x17);
+    }
+    let mut eeprom = At28c256::new();
+    eeprom.persist(&path).unwrap();
+    assert_eq!(eeprom.data()[0x7fff], 0x17);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn sst39sf010_program_requires_unlock() {
+    let mut flash = Sst39sf010::new();
+    flash.write(0x0100u32, 0x00);
+    assert_eq!(flash.read(0x0100u32), 0xff);
+
+    sst_command(&mut flash, 0xa0);
+    flash.write(0x0100u32, 0xf0);
+    assert!(flash.is_busy());
+    assert_eq!(flash.read(0x0100u32) & 0x80, 0x00);
+    wait(&mut flash, 20);
+    assert_eq!(flash.read(0x0100u32), 0xf0);
+
+    // programming can not set bits back
+    sst_command(&mut flash, 0xa0);
+    flash.write(0x0100u32, 0x0f);
+    wait(&mut flash, 20);
+    assert_eq!(flash.read(0x0100u32), 0x00);
+}
+
+#[test]
+fn sst39sf010_sector_erase() {
+    let mut flash = Sst39sf010::new();
+    flash.fill(&[0; 0x20000], 0);
+    // a wrong cycle aborts the sequence
+    sst_command(&mut flash, 0x80);
+    flash.write(0x1f123u32, 0x30);
+    assert!(!flash.is_busy());
+    sst_command(&mut flash, 0x80);
+    flash.write(0x5555u32, 0xaa);
+    flash.write(0x2aaau32, 0x55);
+    // the sector is selected by the address of the last cycle, A16 included
+    flash.write(0x1f123u32, 0x30);
+    assert!(flash.is_busy());
+    wait(&mut flash, 25000);
+    assert_eq!(flash.read(0x1f000u32), 0xff);
+    assert_eq!(flash.read(0x1ffffu32), 0xff);
+    assert_eq!(flash.read(0x1efffu32), 0x00);
+}
+
+#[test]
+fn sst39sf010_software_id() {
+    let mut flash = Sst39sf010::new();
+    sst_command(&mut flash, 0x90);
+    assert_eq!(flash.read(0x0000u32), 0xbf);
+    assert_eq!(flash.read(0x0001u32), 0xb5);
+    flash.write(0x0000u32, 0xf0);
+    assert_eq!(flash.read(0x0000u32), 0xff);
+}
@@ -0,0 +1,114 @@
+use std::io;
+use std::path::Path;
+
+use i2c::I2cDevice;
+use persistent::PersistentImage;
+use Clock;
+
+// 24Cxx serial eeprom, writes wrap inside a page, reads wrap around the whole memory
+pub struct Eeprom24 {
+    image: PersistentImage,
+    page_size: usize,
+    address_bytes: usize,
+    // A2-A0 pins
+    pub chip_address: u8,
+    pointer: usize,
+    // word address bytes still expected in the current write
+    pending_address: usize,
+    // 0 disables the periodic flush
+    pub flush_ticks: u64,
+    ticks: u64,
+    error: Option<io::Error>,
+}
+
+impl Eeprom24 {
+    fn new(size: usize, page_size: usize, address_bytes: usize) -> Eeprom24 {
+        Eeprom24 {
+            // erased cells read as $FF
+            image: PersistentImage::new(size, 0xff),
+            page_size,
+            address_bytes,
+            chip_address: 0,
+            pointer: 0,
+            pending_address: 0,
+            flush_ticks: 0,
+            ticks: 0,
+            error: None,
+        }
+    }
+
+    pub fn new_24c02() -> Eeprom24 {
+        Eeprom24::new(256, 8, 1)
+    }
+
+    pub fn new_24c256() -> Eeprom24 {
+        Eeprom24::new(32768, 64, 2)
+    }
+
+    // loads the content from the file (when it exists), it is written back by
+    // save(), by the periodic flush and on drop
+    pub fn persist<P: AsRef<Path>>(&mut self, filename: P) -> io::Result<()> {
+        self.image.persist(filename.as_ref())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.image.cells
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        self.image.save()
+    }
+
+    // the reason of the last failed periodic flush
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl Clock for Eeprom24 {
+    fn step(&mut self) {
+        if self.flush_ticks == 0 {
+            return;
+        }
+        self.ticks += 1;
+        if self.ticks >= self.flush_ticks {
+            self.ticks = 0;
+            self.error = self.save().err();
+        }
+    }
+}
+
+impl I2cDevice for Eeprom24 {
+    fn address(&self) -> u8 {
+        0x50 | (self.chip_address & 0x07)
+    }
+
+    fn start(&mut self, read: bool) -> bool {
+        if !read {
+            self.pending_address = self.address_bytes;
+        }
+        true
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        let size = self.image.cells.len();
+        if self.pending_address > 0 {
+            if self.pending_address == self.address_bytes {
+                self.pointer = 0;
+            }
+            self.pointer = ((self.pointer << 8) | value as usize) % size;
+            self.pending_address -= 1;
+            return true;
+        }
+        self.image.set(self.pointer, value);
+        let page = self.pointer - self.pointer % self.page_size;
+        self.pointer = page + (self.pointer + 1) % self.page_size;
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.image.cells[self.pointer];
+        self.pointer = (self.pointer + 1) % self.image.cells.len();
+        value
+    }
+}
@@ -0,0 +1,253 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use {Address, AddressBusIO, As};
+
+pub mod eeprom;
+
+// a slave on the bus, byte level: the bus decodes start, stop and acks
+pub trait I2cDevice {
+    // 7 bit address
+    fn address(&self) -> u8;
+    // called when addressed after a (repeated) start, returns the ack
+    fn start(&mut self, read: bool) -> bool;
+    // returns the ack
+    fn write(&mut self, value: u8) -> bool;
+    fn read(&mut self) -> u8;
+    fn stop(&mut self) {}
+}
+
+// devices shared with the host, like eeproms flushed by a scheduler
+impl<D: I2cDevice> I2cDevice for Rc<RefCell<D>> {
+    fn address(&self) -> u8 {
+        self.borrow().address()
+    }
+
+    fn start(&mut self, read: bool) -> bool {
+        self.borrow_mut().start(read)
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        self.borrow_mut().write(value)
+    }
+
+    fn read(&mut self) -> u8 {
+        self.borrow_mut().read()
+    }
+
+    fn stop(&mut self) {
+        self.borrow_mut().stop()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    // receiving the address or a data byte from the master
+    Receive,
+    // the slave acks the received byte
+    SlaveAck,
+    Transmit,
+    // the master acks the transmitted byte
+    MasterAck,
+}
+
+// bit-banged bus: the master drives SCL and SDA (open drain), slaves are driven by the edges.
+// Register $00: bit 0 SCL, bit 1 SDA, reading returns the actual level of the lines
+pub struct I2cBus {
+    devices: Vec<Box<dyn I2cDevice>>,
+    scl: bool,
+    master_sda: bool,
+    slave_sda: bool,
+    state: State,
+    bit: u8,
+    shift: u8,
+    reading: bool,
+    addressing: bool,
+    master_ack: bool,
+    selected: Option<usize>,
+}
+
+const SCL: u8 = 0x01;
+const SDA: u8 = 0x02;
+
+impl I2cBus {
+    pub fn new() -> I2cBus {
+        I2cBus {
+            devices: Vec::new(),
+            scl: true,
+            master_sda: true,
+            slave_sda: true,
+            state: State::Idle,
+            bit: 0,
+            shift: 0,
+            reading: false,
+            addressing: false,
+            master_ack: false,
+            selected: None,
+        }
+    }
+
+    pub fn attach(&mut self, device: Box<dyn I2cDevice>) {
+        self.devices.push(device);
+    }
+
+    // wired-and of the master and the slave
+    pub fn sda(&self) -> bool {
+        self.master_sda && self.slave_sda
+    }
+
+    pub fn scl(&self) -> bool {
+        self.scl
+    }
+
+    // entry point for registers or port pins driving the bus
+    pub fn set_lines(&mut self, scl: bool, sda: bool) {
+        if scl != self.scl {
+            self.master_sda = sda;
+            self.scl = scl;
+            if scl {
+                self.clock_rising();
+            } else {
+                self.clock_falling();
+            }
+        } else if sda != self.master_sda {
+            self.master_sda = sda;
+            if scl {
+                if sda {
+                    self.stop();
+                } else {
+                    self.start();
+                }
+            }
+        }
+    }
+
+    fn start(&mut self) {
+        self.state = State::Receive;
+        self.addressing = true;
+        self.bit = 0;
+        self.shift = 0;
+        self.slave_sda = true;
+    }
+
+    fn stop(&mut self) {
+        if let Some(selected) = self.selected.take() {
+            self.devices[selected].stop();
+        }
+        self.state = State::Idle;
+        self.slave_sda = true;
+    }
+
+    fn clock_rising(&mut self) {
+        match self.state {
+            State::Receive => {
+                self.shift = (self.shift << 1) | self.sda() as u8;
+                self.bit += 1;
+            }
+            State::Transmit => self.bit += 1,
+            State::MasterAck => self.master_ack = !self.sda(),
+            _ => (),
+        }
+    }
+
+    fn clock_falling(&mut self) {
+        match self.state {
+            State::Receive if self.bit == 8 => {
+                let ack = if self.addressing {
+                    self.address(self.shift)
+                } else {
+                    match self.selected {
+                        Some(selected) => self.devices[selected].write(self.shift),
+                        None => false,
+                    }
+                };
+                self.slave_sda = !ack;
+                self.state = if ack { State::SlaveAck } else { State::Idle };
+            }
+            State::SlaveAck => {
+                if self.reading {
+                    self.load();
+                } else {
+                    self.slave_sda = true;
+                    self.state = State::Receive;
+                    self.bit = 0;
+                    self.shift = 0;
+                }
+            }
+            State::Transmit => {
+                if self.bit == 8 {
+                    self.slave_sda = true;
+                    self.state = State::MasterAck;
+                } else {
+                    self.slave_sda = self.shift & (0x80 >> self.bit) != 0;
+                }
+            }
+            State::MasterAck => {
+                if self.master_ack {
+                    self.load();
+                } else {
+                    // the master ends the read, waiting for the stop
+                    self.state = State::Idle;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn address(&mut self, value: u8) -> bool {
+        self.addressing = false;
+        self.reading = value & 0x01 != 0;
+        let address = value >> 1;
+        let reading = self.reading;
+        self.selected = None;
+        for (index, device) in self.devices.iter_mut().enumerate() {
+            if device.address() == address && device.start(reading) {
+                self.selected = Some(index);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn load(&mut self) {
+        self.shift = match self.selected {
+            Some(selected) => self.devices[selected].read(),
+            None => 0xff,
+        };
+        self.bit = 0;
+        self.slave_sda = self.shift & 0x80 != 0;
+        self.state = State::Transmit;
+    }
+}
+
+impl Default for I2cBus {
+    fn default() -> I2cBus {
+        I2cBus::new()
+    }
+}
+
+impl<T: Address + As<usize>> AddressBusIO<T, u8> for I2cBus {
+    fn write(&mut self, address: T, value: u8) {
+        if address.as_() == 0x00 {
+            self.set_lines(value & SCL != 0, value & SDA != 0);
+        }
+    }
+
+    fn read(&mut self, address: T) -> u8 {
+        if address.as_() != 0x00 {
+            return 0;
+        }
+        let mut value = 0;
+        if self.scl {
+            value |= SCL;
+        }
+        if self.sda() {
+            value |= SDA;
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use i2c::eeprom::Eeprom24;
+use i2c::I2cBus;
+use utils::temp_path;
+use {AddressBusIO, Clock};
+
+// bit-banging helpers going through the register, like a driver would
+fn lines(bus: &mut I2cBus, scl: bool, sda: bool) {
+    bus.write(0x00u16, (scl as u8) | ((sda as u8) << 1));
+}
+
+fn start(bus: &mut I2cBus) {
+    lines(bus, true, true);
+    lines(bus, true, false);
+    lines(bus, false, false);
+}
+
+fn stop(bus: &mut I2cBus) {
+    lines(bus, false, false);
+    lines(bus, true, false);
+    lines(bus, true, true);
+}
+
+// returns the ack
+fn write_byte(bus: &mut I2cBus, value: u8) -> bool {
+    for bit in 0..8 {
+        let sda = value & (0x80 >> bit) != 0;
+        lines(bus, false, sda);
+        lines(bus, true, sda);
+        lines(bus, false, sda);
+    }
+    lines(bus, false, true);
+    lines(bus, true, true);
+    let ack = bus.read(0x00u16) & 0x02 == 0;
+    lines(bus, false, true);
+    ack
+}
+
+fn read_byte(bus: &mut I2cBus, ack: bool) -> u8 {
+    let mut value = 0;
+    for _ in 0..8 {
+        lines(bus, false, true);
+        lines(bus, true, true);
+        value = (value << 1) | ((bus.read(0x00u16) >> 1) & 0x01);
+        lines(bus, false, true);
+    }
+    lines(bus, false, !ack);
+    lines(bus, true, !ack);
+    lines(bus, false, !ack);
+    value
+}
+
+#[test]
+fn unknown_address_is_not_acked() {
+    let mut bus = I2cBus::new();
+    bus.attach(Box::new(Eeprom24::new_24c02()));
+    start(&mut bus);
+    assert!(!write_byte(&mut bus, 0x51 << 1));
+    stop(&mut bus);
+    start(&mut bus);
+    assert!(write_byte(&mut bus, 0x50 << 1));
+    stop(&mut bus);
+}
+
+#[test]
+fn eeprom_24c02_page_write_and_read() {
+    let mut bus = I2cBus::new();
+    bus.attach(Box::new(Eeprom24::new_24c02()));
+
+    // page write at $06, wraps to $00 after $07
+    start(&mut bus);
+    assert!(write_byte(&mut bus, 0xa0));
+    assert!(write_byte(&mut bus, 0x06));
+    for value in 1..4 {
+        assert!(write_byte(&mut bus, value));
+    }
+    stop(&mut bus);
+
+    // random read: dummy write of the address, repeated start, sequential read
+    start(&mut bus);
+    assert!(write_byte(&mut bus, 0xa0));
+    assert!(write_byte(&mut bus, 0x06));
+    start(&mut bus);
+    assert!(write_byte(&mut bus, 0xa1));
+    assert_eq!(read_byte(&mut bus, true), 1);
+    assert_eq!(read_byte(&mut bus, true), 2);
+    assert_eq!(read_byte(&mut bus, false), 0xff);
+    stop(&mut bus);
+
+    start(&mut bus);
+    assert!(write_byte(&mut bus, 0xa0));
+    assert!(write_byte(&mut bus, 0x00));
+    start(&mut bus);
+    assert!(write_byte(&mut bus, 0xa1));
+    assert_eq!(read_byte(&mut bus, false), 3);
+    stop(&mut bus);
+}
+
+#[test]
+fn eeprom_24c256_persists() {
+    let path = temp_path("eeprom_24c256");
+
+    let mut eeprom = Eeprom24::new_24c256();
+    eeprom.persist(&path).unwrap();
+    eeprom.flush_ticks = 10;
+    let eeprom = Rc::new(RefCell::new(eeprom));
+    let mut bus = I2cBus::new();
+    bus.attach(Box::new(eeprom.clone()));
+    start(&mut bus);
+    assert!(write_byte(&mut bus, 0xa0));
+    assert!(write_byte(&mut bus, 0x12));
+    assert!(write_byte(&mut bus, 0x34));
+    assert!(write_byte(&mut bus, 0x17));
+    stop(&mut bus);
+    // written back by the periodic flush
+    assert!(fs::read(&path).is_err());
+    for _ in 0..10 {
+        eeprom.borrow_mut().step();
+    }
+    assert!(eeprom.borrow().error().is_none());
+
+    let data = fs::read(&path).unwrap();
+    assert_eq!(data.len(), 32768);
+    assert_eq!(data[0x1234], 0x17);
+
+    let mut eeprom = Eeprom24::new_24c256();
+    eeprom.persist(&path).unwrap();
+    assert_eq!(eeprom.data()[0x1234], 0x17);
+    fs::remove_file(&path).unwrap();
+}
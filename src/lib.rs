@@ -4,7 +4,12 @@ extern crate rand;
 pub use num_traits::AsPrimitive as As;
 use num_traits::{NumAssign, PrimInt};
 
+use std::error;
+use std::fmt;
 use std::fmt::{Display, LowerHex, UpperHex};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub trait Address:
     PrimInt + NumAssign + Display + LowerHex + UpperHex + Sync + Send + 'static
@@ -16,20 +21,195 @@ pub trait Data: Address {}
 impl<T: PrimInt + NumAssign + Display + LowerHex + UpperHex + Sync + Send + 'static> Address for T {}
 impl<T: Address> Data for T {}
 
+#[derive(Debug)]
+pub enum BusError {
+    UnmappedRead(u64),
+    UnmappedWrite(u64),
+    OutOfRange(u64),
+    Io(io::Error),
+}
+
+impl BusError {
+    pub fn unmapped_read<T: Address>(address: T) -> BusError {
+        BusError::UnmappedRead(address.to_u64().unwrap_or(0))
+    }
+
+    pub fn unmapped_write<T: Address>(address: T) -> BusError {
+        BusError::UnmappedWrite(address.to_u64().unwrap_or(0))
+    }
+
+    pub fn out_of_range<T: Address>(address: T) -> BusError {
+        BusError::OutOfRange(address.to_u64().unwrap_or(0))
+    }
+}
+
+impl Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::UnmappedRead(address) => write!(f, "unmapped read at ${:X}", address),
+            BusError::UnmappedWrite(address) => write!(f, "unmapped write at ${:X}", address),
+            BusError::OutOfRange(address) => write!(f, "address ${:X} out of range", address),
+            BusError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl error::Error for BusError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BusError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BusError {
+    fn from(err: io::Error) -> BusError {
+        BusError::Io(err)
+    }
+}
+
 pub trait AddressBusIO<T: Address, U: Data> {
     fn read(&mut self, _address: T) -> U {
         U::zero()
     }
     fn write(&mut self, _address: T, _value: U) {}
+    // fallible variants, devices that can fail should override them
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        Ok(self.read(address))
+    }
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.write(address, value);
+        Ok(())
+    }
+    // extra cycles the last accesses cost, cleared on every call
+    fn take_wait_states(&mut self) -> u64 {
+        0
+    }
+    // the content of the address without any side effect, None when the device can not tell
+    fn peek(&self, _address: T) -> Option<U> {
+        None
+    }
 }
 
 pub trait AddressBusBlockIO<T: Address, U: Data> {
-    fn read(&mut self, address: T, buffer: &mut [U]);
-    fn write(&mut self, address: T, buffer: &[U]);
+    fn read(&mut self, address: T, buffer: &mut [U]) -> io::Result<()>;
+    fn write(&mut self, address: T, buffer: &[U]) -> io::Result<()>;
+}
+
+// wraps around the end of the address space like a real bus, offsets longer
+// than the address space wrap too
+pub(crate) fn offset_address<T: Address>(address: T, offset: usize) -> T {
+    let mask = T::max_value().to_u128().unwrap();
+    let address = address.to_u128().unwrap().wrapping_add(offset as u128);
+    T::from(address & mask).unwrap()
+}
+
+// helpers available on every bus, the u16 ones combine two consecutive cells
+pub trait AddressBusExt<T: Address, U: Data>: AddressBusIO<T, U> {
+    fn read_u16_le(&mut self, address: T) -> u16
+    where
+        U: As<u16>,
+    {
+        let low: u16 = self.read(address).as_();
+        let high: u16 = self.read(offset_address(address, 1)).as_();
+        (high << 8) | low
+    }
+
+    fn read_u16_be(&mut self, address: T) -> u16
+    where
+        U: As<u16>,
+    {
+        let high: u16 = self.read(address).as_();
+        let low: u16 = self.read(offset_address(address, 1)).as_();
+        (high << 8) | low
+    }
+
+    // like read_u16_le but stops at the first cell the bus refuses
+    fn try_read_u16_le(&mut self, address: T) -> Result<u16, BusError>
+    where
+        U: As<u16>,
+    {
+        let low: u16 = self.try_read(address)?.as_();
+        let high: u16 = self.try_read(offset_address(address, 1))?.as_();
+        Ok((high << 8) | low)
+    }
+
+    fn try_read_u16_be(&mut self, address: T) -> Result<u16, BusError>
+    where
+        U: As<u16>,
+    {
+        let high: u16 = self.try_read(address)?.as_();
+        let low: u16 = self.try_read(offset_address(address, 1))?.as_();
+        Ok((high << 8) | low)
+    }
+
+    fn write_u16_le(&mut self, address: T, value: u16)
+    where
+        u16: As<U>,
+    {
+        self.write(address, (value & 0xff).as_());
+        self.write(offset_address(address, 1), (value >> 8).as_());
+    }
+
+    fn write_u16_be(&mut self, address: T, value: u16)
+    where
+        u16: As<U>,
+    {
+        self.write(address, (value >> 8).as_());
+        self.write(offset_address(address, 1), (value & 0xff).as_());
+    }
+
+    fn read_block(&mut self, address: T, buffer: &mut [U]) {
+        for (offset, value) in buffer.iter_mut().enumerate() {
+            *value = self.read(offset_address(address, offset));
+        }
+    }
+
+    fn write_block(&mut self, address: T, buffer: &[U]) {
+        for (offset, value) in buffer.iter().enumerate() {
+            self.write(offset_address(address, offset), *value);
+        }
+    }
+
+    // like write_block but stops at the first cell the bus refuses
+    fn load(&mut self, data: &[U], base: T) -> Result<(), BusError> {
+        for (offset, value) in data.iter().enumerate() {
+            self.try_write(offset_address(base, offset), *value)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Address, U: Data, B: AddressBusIO<T, U> + ?Sized> AddressBusExt<T, U> for B {}
+
+// a wire shared between devices, like a bus request or an interrupt request
+#[derive(Clone, Default)]
+pub struct Line {
+    state: Arc<AtomicBool>,
+}
+
+impl Line {
+    pub fn new() -> Line {
+        Line::default()
+    }
+
+    pub fn set(&self, state: bool) {
+        self.state.store(state, Ordering::SeqCst);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.state.load(Ordering::SeqCst)
+    }
 }
 
 pub trait Clock {
     fn step(&mut self);
+    // steps and returns the cycles consumed, devices with variable timing should override it
+    fn tick(&mut self) -> u64 {
+        self.step();
+        1
+    }
 }
 
 pub trait Interrupt<T: Address> {
@@ -46,6 +226,71 @@ pub trait Debug<T: Address, U: Data> {
     fn next(&mut self);
     fn set_code_breakpoint(&mut self, bool);
     fn is_code_breakpoint_requested(&mut self) -> bool;
+    // the address of a symbol, for debuggers accepting labels
+    fn resolve(&self, _name: &str) -> Option<T> {
+        None
+    }
+    // the symbol describing an address, like label or label+offset
+    fn symbol(&self, _address: T) -> Option<String> {
+        None
+    }
+    // the registers of the device, indexes are the ones of get/set_register
+    fn registers(&self) -> Vec<RegisterInfo> {
+        Vec::new()
+    }
+    fn get_register(&self, _index: usize) -> Option<u64> {
+        None
+    }
+    // false when the register does not exist
+    fn set_register(&mut self, _index: usize, _value: u64) -> bool {
+        false
+    }
+    fn register_index(&self, name: &str) -> Option<usize> {
+        self.registers()
+            .iter()
+            .position(|register| register.name.eq_ignore_ascii_case(name))
+    }
+    // the instruction at the cursor, for stepping over calls and out of subroutines
+    fn instruction_kind(&mut self) -> InstructionKind<T> {
+        InstructionKind::Other
+    }
+    // for cpus whose stack grows down, so that finish can tell when the current frame is left
+    fn stack_pointer(&self) -> Option<u64> {
+        None
+    }
+    // the value of a register by name (case insensitive), for breakpoint conditions
+    fn register(&self, name: &str) -> Option<u64> {
+        self.register_index(name)
+            .and_then(|index| self.get_register(index))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InstructionKind<T> {
+    // with the address execution continues from once the subroutine returns
+    Call(T),
+    Return,
+    Other,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RegisterInfo {
+    pub name: &'static str,
+    pub bits: u32,
+}
+
+impl RegisterInfo {
+    pub fn new(name: &'static str, bits: u32) -> RegisterInfo {
+        RegisterInfo { name, bits }
+    }
+
+    pub fn mask(&self) -> u64 {
+        if self.bits >= 64 {
+            u64::MAX
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
 }
 
 pub mod adapter;
@@ -53,15 +298,24 @@ pub mod audio;
 pub mod chip8;
 pub mod debugger;
 pub mod dma;
+pub mod flash;
 pub mod graphics;
+pub mod i2c;
 pub mod input;
+pub mod loader;
+pub mod machine;
 pub mod memcontroller;
 pub mod mos6502;
+mod persistent;
 pub mod ram;
 pub mod random;
 pub mod rom;
+pub mod scheduler;
+pub mod spi;
 pub mod storage;
+pub mod symbols;
 pub mod timer;
+pub mod trace;
 pub mod unixterm;
 pub mod utils;
 
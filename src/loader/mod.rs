@@ -0,0 +1,376 @@
+use std::fs;
+use std::path::Path;
+
+use {Address, AddressBusExt, AddressBusIO, BusError};
+
+// a run of bytes to be stored at consecutive addresses
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub entry: Option<u32>,
+}
+
+impl Image {
+    pub fn new() -> Image {
+        Image::default()
+    }
+
+    // contiguous data is merged in the last segment
+    pub fn add(&mut self, address: u32, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(segment) = self.segments.last_mut() {
+            if segment.address as usize + segment.data.len() == address as usize {
+                segment.data.extend_from_slice(data);
+                return;
+            }
+        }
+        self.segments.push(Segment {
+            address,
+            data: data.to_vec(),
+        });
+    }
+
+    // writes every segment through the bus, segment addresses must fit in T
+    pub fn load_into<T: Address, B: AddressBusIO<T, u8> + ?Sized>(
+        &self,
+        bus: &mut B,
+    ) -> Result<(), BusError> {
+        for segment in &self.segments {
+            let base = match T::from(segment.address) {
+                Some(base) => base,
+                None => return Err(BusError::out_of_range(segment.address)),
+            };
+            bus.load(&segment.data, base)?;
+        }
+        Ok(())
+    }
+
+    // the segments below address and the ones from address up, a segment across it is cut
+    pub fn split(&self, address: u32) -> (Image, Image) {
+        let mut below = Image::new();
+        let mut above = Image::new();
+        for segment in &self.segments {
+            let start = u64::from(segment.address);
+            let end = start + segment.data.len() as u64;
+            if end <= u64::from(address) {
+                below.add(segment.address, &segment.data);
+            } else if start >= u64::from(address) {
+                above.add(segment.address, &segment.data);
+            } else {
+                let length = (u64::from(address) - start) as usize;
+                below.add(segment.address, &segment.data[..length]);
+                above.add(address, &segment.data[length..]);
+            }
+        }
+        below.entry = self.entry;
+        above.entry = self.entry;
+        (below, above)
+    }
+
+    // a raw image of size bytes starting at base, for Rom::new and Ram::fill
+    pub fn flatten(&self, base: u32, size: usize, fill: u8) -> Result<Vec<u8>, String> {
+        let mut data = vec![fill; size];
+        for segment in &self.segments {
+            let start = u64::from(segment.address);
+            let end = start + segment.data.len() as u64;
+            if start < u64::from(base) || end > u64::from(base) + size as u64 {
+                return Err(format!(
+                    "segment ${:04X}-${:04X} outside of ${:04X}-${:04X}",
+                    start,
+                    end - 1,
+                    base,
+                    u64::from(base) + size as u64 - 1
+                ));
+            }
+            let offset = (start - u64::from(base)) as usize;
+            data[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        Ok(data)
+    }
+}
+
+fn hex_bytes(text: &str, line: usize) -> Result<Vec<u8>, String> {
+    if text.len() & 1 != 0 || !text.is_ascii() {
+        return Err(format!("line {}: invalid hex digits", line));
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 2);
+    for index in (0..text.len()).step_by(2) {
+        match u8::from_str_radix(&text[index..index + 2], 16) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return Err(format!("line {}: invalid hex digits", line)),
+        }
+    }
+    Ok(bytes)
+}
+
+fn be_value(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | u32::from(*byte))
+}
+
+fn le_word(data: &[u8], offset: usize) -> Result<u32, String> {
+    match data.get(offset..offset + 2) {
+        Some(word) => Ok(u32::from(word[0]) | (u32::from(word[1]) << 8)),
+        None => Err("unexpected end of file".to_string()),
+    }
+}
+
+fn le_long(data: &[u8], offset: usize) -> Result<u32, String> {
+    Ok(le_word(data, offset)? | (le_word(data, offset + 2)? << 16))
+}
+
+// :LLAAAATT<data>CC records, with segment (02/03) and linear (04/05) extensions
+pub fn parse_intel_hex(text: &str) -> Result<Image, String> {
+    let mut image = Image::new();
+    let mut base: u32 = 0;
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = raw_line.trim();
+        if record.is_empty() {
+            continue;
+        }
+        if !record.starts_with(':') {
+            return Err(format!("line {}: missing record mark", line));
+        }
+        let bytes = hex_bytes(&record[1..], line)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(format!("line {}: invalid record length", line));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(format!("line {}: checksum mismatch", line));
+        }
+        let address = be_value(&bytes[1..3]);
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data.len()) {
+            (0x00, _) => image.add(base.wrapping_add(address), data),
+            (0x01, _) => break,
+            (0x02, 2) => base = be_value(data) << 4,
+            (0x03, 4) => image.entry = Some((be_value(&data[0..2]) << 4) + be_value(&data[2..4])),
+            (0x04, 2) => base = be_value(data) << 16,
+            (0x05, 4) => image.entry = Some(be_value(data)),
+            (kind, _) => return Err(format!("line {}: invalid record type {:02X}", line, kind)),
+        }
+    }
+    Ok(image)
+}
+
+// S1/S2/S3 data records with 16, 24 and 32 bit addresses, S9/S8/S7 set the entry point
+pub fn parse_srecord(text: &str) -> Result<Image, String> {
+    let mut image = Image::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = raw_line.trim();
+        if record.is_empty() {
+            continue;
+        }
+        if !record.starts_with('S') || record.len() < 2 {
+            return Err(format!("line {}: missing record mark", line));
+        }
+        let kind = record.as_bytes()[1];
+        let bytes = hex_bytes(&record[2..], line)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(format!("line {}: invalid record length", line));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0xff {
+            return Err(format!("line {}: checksum mismatch", line));
+        }
+        let address_size = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => {
+                return Err(format!(
+                    "line {}: invalid record type S{}",
+                    line, kind as char
+                ))
+            }
+        };
+        if bytes.len() < address_size + 2 {
+            return Err(format!("line {}: invalid record length", line));
+        }
+        let address = be_value(&bytes[1..=address_size]);
+        let data = &bytes[address_size + 1..bytes.len() - 1];
+        match kind {
+            b'1' | b'2' | b'3' => image.add(address, data),
+            b'7' | b'8' | b'9' => image.entry = Some(address),
+            // header and record counts
+            _ => (),
+        }
+    }
+    Ok(image)
+}
+
+// a little endian load address followed by the data
+pub fn parse_prg(data: &[u8]) -> Result<Image, String> {
+    let mut image = Image::new();
+    image.add(le_word(data, 0)?, &data[2..]);
+    Ok(image)
+}
+
+// the non-C64 marker and the magic, followed by the version and the mode
+const O65_MAGIC: [u8; 5] = [0x01, 0x00, b'o', b'6', b'5'];
+const O65_SIZE_32: u16 = 0x2000;
+
+// text and data segments of an o65 file, loaded at their assembled addresses
+// (relocation tables are ignored), the entry point is the start of the text segment
+pub fn parse_o65(data: &[u8]) -> Result<Image, String> {
+    if !data.starts_with(&O65_MAGIC) {
+        return Err("missing o65 header".to_string());
+    }
+    let mode = le_word(data, 6)? as u16;
+    let field = |index: usize| -> Result<u32, String> {
+        if mode & O65_SIZE_32 != 0 {
+            le_long(data, 8 + index * 4)
+        } else {
+            le_word(data, 8 + index * 2)
+        }
+    };
+    let text_base = field(0)?;
+    let text_length = field(1)? as usize;
+    let data_base = field(2)?;
+    let data_length = field(3)? as usize;
+
+    // tbase, tlen, dbase, dlen, bbase, blen, zbase, zlen and stack
+    let mut offset = 8 + 9 * if mode & O65_SIZE_32 != 0 { 4 } else { 2 };
+    // header options are length prefixed, a zero length ends them
+    loop {
+        match data.get(offset) {
+            Some(0) => break,
+            Some(length) => offset += *length as usize,
+            None => return Err("unexpected end of file".to_string()),
+        }
+    }
+    offset += 1;
+
+    let mut image = Image::new();
+    match data.get(offset..offset + text_length) {
+        Some(text) => image.add(text_base, text),
+        None => return Err("truncated text segment".to_string()),
+    }
+    offset += text_length;
+    match data.get(offset..offset + data_length) {
+        Some(segment) => image.add(data_base, segment),
+        None => return Err("truncated data segment".to_string()),
+    }
+    image.entry = Some(text_base);
+    Ok(image)
+}
+
+const XEX_RUNAD: u32 = 0x02e0;
+
+// $FFFF followed by start/end address pairs and their data, a segment covering
+// RUNAD sets the entry point
+pub fn parse_xex(data: &[u8]) -> Result<Image, String> {
+    if le_word(data, 0)? != 0xffff {
+        return Err("missing xex header".to_string());
+    }
+    let mut image = Image::new();
+    let mut offset = 2;
+    while offset < data.len() {
+        let mut start = le_word(data, offset)?;
+        // the header can be repeated before any segment
+        if start == 0xffff {
+            offset += 2;
+            start = le_word(data, offset)?;
+        }
+        let end = le_word(data, offset + 2)?;
+        if end < start {
+            return Err(format!(
+                "segment ${:04X}-${:04X} ends before start",
+                start, end
+            ));
+        }
+        offset += 4;
+        let length = (end - start + 1) as usize;
+        let segment = match data.get(offset..offset + length) {
+            Some(segment) => segment,
+            None => return Err(format!("truncated segment ${:04X}-${:04X}", start, end)),
+        };
+        if start <= XEX_RUNAD && end > XEX_RUNAD {
+            image.entry = Some(le_word(segment, (XEX_RUNAD - start) as usize)?);
+        }
+        image.add(start, segment);
+        offset += length;
+    }
+    Ok(image)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    IntelHex,
+    SRecord,
+    Prg,
+    O65,
+    Xex,
+}
+
+impl Format {
+    pub fn from_extension<P: AsRef<Path>>(filename: P) -> Option<Format> {
+        let extension = filename
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("hex") | Some("ihx") | Some("ihex") => Some(Format::IntelHex),
+            Some("s19") | Some("s28") | Some("s37") | Some("srec") | Some("mot") => {
+                Some(Format::SRecord)
+            }
+            Some("prg") => Some(Format::Prg),
+            Some("o65") => Some(Format::O65),
+            Some("xex") => Some(Format::Xex),
+            _ => None,
+        }
+    }
+
+    // prg files have no signature
+    pub fn from_content(data: &[u8]) -> Option<Format> {
+        if data.starts_with(b":") {
+            Some(Format::IntelHex)
+        } else if data.starts_with(b"S0") || data.starts_with(b"S1") {
+            Some(Format::SRecord)
+        } else if data.starts_with(&O65_MAGIC) {
+            Some(Format::O65)
+        } else if data.starts_with(&[0xff, 0xff]) {
+            Some(Format::Xex)
+        } else {
+            None
+        }
+    }
+
+    pub fn parse(self, data: &[u8]) -> Result<Image, String> {
+        match self {
+            Format::IntelHex => parse_intel_hex(&String::from_utf8_lossy(data)),
+            Format::SRecord => parse_srecord(&String::from_utf8_lossy(data)),
+            Format::Prg => parse_prg(data),
+            Format::O65 => parse_o65(data),
+            Format::Xex => parse_xex(data),
+        }
+    }
+}
+
+// the format is chosen by extension, falling back to the content
+pub fn load_file<P: AsRef<Path>>(filename: P) -> Result<Image, String> {
+    let path = filename.as_ref();
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => return Err(format!("unable to read {}: {}", path.display(), err)),
+    };
+    let result = match Format::from_extension(path).or_else(|| Format::from_content(&data)) {
+        Some(format) => format.parse(&data),
+        None => Err("unknown format".to_string()),
+    };
+    result.map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests;
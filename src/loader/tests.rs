@@ -0,0 +1,146 @@
+use std::fs;
+
+use loader::{
+    load_file, parse_intel_hex, parse_o65, parse_prg, parse_srecord, parse_xex, Image, Segment,
+};
+use ram::Ram;
+use utils::temp_path;
+use {AddressBusIO, BusError};
+
+#[test]
+fn intel_hex() {
+    let image = parse_intel_hex(
+        ":03C00000A9016033\n\
+         :02C00300010238\n\
+         :020000040001F9\n\
+         :0100000017E8\n\
+         :04000005C0001234F1\n\
+         :00000001FF\n",
+    )
+    .unwrap();
+    assert_eq!(
+        image.segments,
+        vec![
+            Segment {
+                address: 0xc000,
+                data: vec![0xa9, 0x01, 0x60, 0x01, 0x02],
+            },
+            Segment {
+                address: 0x10000,
+                data: vec![0x17],
+            },
+        ]
+    );
+    assert_eq!(image.entry, Some(0xc0001234));
+    assert!(parse_intel_hex(":03C00000A9016034\n").is_err());
+}
+
+#[test]
+fn srecord() {
+    let image = parse_srecord(
+        "S00600004844521B\n\
+         S106C000A901602F\n\
+         S2060100001122C5\n\
+         S9030200FA\n",
+    )
+    .unwrap();
+    assert_eq!(image.segments[0].address, 0xc000);
+    assert_eq!(image.segments[0].data, vec![0xa9, 0x01, 0x60]);
+    assert_eq!(image.segments[1].address, 0x10000);
+    assert_eq!(image.segments[1].data, vec![0x11, 0x22]);
+    assert_eq!(image.entry, Some(0x0200));
+    assert!(parse_srecord("S106C000A901602E\n").is_err());
+}
+
+#[test]
+fn prg() {
+    let image = parse_prg(&[0x01, 0x08, 0x0b, 0x08]).unwrap();
+    assert_eq!(image.segments[0].address, 0x0801);
+    assert_eq!(image.segments[0].data, vec![0x0b, 0x08]);
+    assert_eq!(image.entry, None);
+    assert!(parse_prg(&[0x01]).is_err());
+}
+
+#[test]
+fn o65() {
+    let mut data = vec![0x01, 0x00, b'o', b'6', b'5', 0x00, 0x00, 0x00];
+    // tbase, tlen, dbase, dlen, bbase, blen, zbase, zlen, stack
+    for word in &[0xc000u16, 3, 0x0200, 2, 0x0300, 0, 0x0002, 0, 0] {
+        data.push(*word as u8);
+        data.push((*word >> 8) as u8);
+    }
+    // a filename option
+    data.extend_from_slice(&[0x04, 0x00, b'a', 0x00, 0x00]);
+    data.extend_from_slice(&[0xa9, 0x01, 0x60, 0x17, 0x22]);
+    let image = parse_o65(&data).unwrap();
+    assert_eq!(image.segments[0].address, 0xc000);
+    assert_eq!(image.segments[0].data, vec![0xa9, 0x01, 0x60]);
+    assert_eq!(image.segments[1].address, 0x0200);
+    assert_eq!(image.segments[1].data, vec![0x17, 0x22]);
+    assert_eq!(image.entry, Some(0xc000));
+    assert!(parse_o65(&data[..data.len() - 1]).is_err());
+}
+
+#[test]
+fn xex() {
+    let image = parse_xex(&[
+        0xff, 0xff, 0x00, 0x20, 0x01, 0x20, 0xa9, 0x01, 0xff, 0xff, 0xe0, 0x02, 0xe1, 0x02, 0x00,
+        0x20,
+    ])
+    .unwrap();
+    assert_eq!(image.segments.len(), 2);
+    assert_eq!(image.segments[0].address, 0x2000);
+    assert_eq!(image.entry, Some(0x2000));
+}
+
+#[test]
+fn load_into_bus_and_flatten() {
+    let mut image = Image::new();
+    image.add(0x04, &[1, 2]);
+    image.add(0x06, &[3]);
+    image.add(0x0c, &[4]);
+    assert_eq!(image.segments.len(), 2);
+
+    let mut ram: Ram<u8> = Ram::new(16);
+    image.load_into::<u16, _>(&mut ram).unwrap();
+    assert_eq!(ram.read(0x06u16), 3);
+    assert_eq!(ram.read(0x0cu16), 4);
+
+    image.add(0x10, &[5]);
+    match image.load_into::<u16, _>(&mut ram) {
+        Err(BusError::OutOfRange(0x10)) => (),
+        _ => panic!("expected an out of range write"),
+    }
+
+    assert_eq!(
+        image.flatten(0x04, 13, 0xff).unwrap(),
+        vec![1, 2, 3, 0xff, 0xff, 0xff, 0xff, 0xff, 4, 0xff, 0xff, 0xff, 5]
+    );
+    assert!(image.flatten(0x05, 16, 0xff).is_err());
+}
+
+#[test]
+fn split_at_address() {
+    let mut image = Image::new();
+    image.add(0x0200, &[1]);
+    image.add(0xbffe, &[2, 3, 4]);
+    image.add(0xfffc, &[5]);
+    image.entry = Some(0x0200);
+    let (ram, rom) = image.split(0xc000);
+    assert_eq!(ram.segments.len(), 2);
+    assert_eq!(ram.segments[1].data, vec![2, 3]);
+    assert_eq!(rom.segments[0].address, 0xc000);
+    assert_eq!(rom.segments[0].data, vec![4]);
+    assert_eq!(rom.segments[1].address, 0xfffc);
+    assert_eq!(rom.entry, Some(0x0200));
+}
+
+#[test]
+fn load_file_by_content() {
+    let path = temp_path("loader_image");
+    fs::write(&path, ":03C00000A9016033\n:00000001FF\n").unwrap();
+    assert_eq!(load_file(&path).unwrap().segments[0].address, 0xc000);
+    fs::write(&path, [0x00, 0x01]).unwrap();
+    assert!(load_file(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
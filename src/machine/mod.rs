@@ -0,0 +1,355 @@
+use std::cell::RefCell;
+use std::fs;
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use adapter::BusAdapterBoxed;
+use audio::Piano;
+use dma::DmaMemory;
+use memcontroller::{MemoryControllerShared, UnmappedRead};
+use mos6502::MOS6502;
+use ram::Ram;
+use random::Random;
+use rom::Rom;
+use unixterm::UnixTerm;
+use utils::to_number;
+use {Address, AddressBusExt, AddressBusIO, Clock, Interrupt, Line};
+
+// a [name] block of key = value pairs from a machine description
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        for (entry_key, entry_value) in &self.entries {
+            if entry_key == key {
+                return Some(entry_value);
+            }
+        }
+        None
+    }
+
+    fn number<T: Address<FromStrRadixErr = ParseIntError>>(
+        &self,
+        key: &str,
+    ) -> Result<Option<T>, String> {
+        match self.get(key) {
+            Some(value) => match to_number(value) {
+                Ok(number) => Ok(Some(number)),
+                Err(err) => Err(format!(
+                    "[{}] invalid {} {}: {}",
+                    self.name, key, value, err
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn required<T: Address<FromStrRadixErr = ParseIntError>>(
+        &self,
+        key: &str,
+    ) -> Result<T, String> {
+        match self.number(key)? {
+            Some(number) => Ok(number),
+            None => Err(format!("[{}] missing {}", self.name, key)),
+        }
+    }
+}
+
+// INI-like format: [section] headers, key = value pairs, # or ; comments
+pub fn parse(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            if !line.ends_with(']') {
+                return Err(format!("line {}: unterminated section header", index + 1));
+            }
+            sections.push(Section {
+                name: line[1..line.len() - 1].trim().to_string(),
+                entries: Vec::new(),
+            });
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap().trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => return Err(format!("line {}: expected key = value", index + 1)),
+        };
+        match sections.last_mut() {
+            Some(section) => section.entries.push((key.to_string(), value.to_string())),
+            None => return Err(format!("line {}: {} outside of a section", index + 1, key)),
+        }
+    }
+    Ok(sections)
+}
+
+struct PeriodicInterrupt {
+    line: u16,
+    period: u64,
+    next: u64,
+}
+
+// a device output wired to the NMI input, which fires on the rising edge
+struct EdgeInterrupt {
+    line: Line,
+    level: bool,
+}
+
+pub struct Machine {
+    pub cpu: MOS6502<MemoryControllerShared<u16, u8>>,
+    pub hz: u32,
+    interrupts: Vec<PeriodicInterrupt>,
+    // stepped for every cycle of the cpu
+    devices: Vec<Rc<RefCell<dyn Clock>>>,
+    nmi: Vec<EdgeInterrupt>,
+}
+
+impl Machine {
+    pub fn step(&mut self) {
+        let ticks = self.cpu.tick();
+        for device in &self.devices {
+            for _ in 0..ticks {
+                device.borrow_mut().step();
+            }
+        }
+        for nmi in &mut self.nmi {
+            let level = nmi.line.is_set();
+            if level && !nmi.level {
+                self.cpu.raise(6);
+            }
+            nmi.level = level;
+        }
+        for interrupt in &mut self.interrupts {
+            if self.cpu.ticks >= interrupt.next {
+                interrupt.next += interrupt.period;
+                self.cpu.raise(interrupt.line);
+            }
+        }
+    }
+
+    // the time the executed cycles take on the real machine, for throttling
+    pub fn emulated_time(&self) -> Duration {
+        let nanos = u128::from(self.cpu.ticks) * 1_000_000_000 / u128::from(self.hz.max(1));
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+// builds the mos6502 machines made of the stock devices: atari2600 (TIA, RIOT
+// and their mirrors) and 6502synth (a host thread timer and a separate audio
+// bus) still need their own binaries
+pub struct MachineBuilder {
+    sections: Vec<Section>,
+    base_path: PathBuf,
+}
+
+impl MachineBuilder {
+    pub fn parse(text: &str) -> Result<MachineBuilder, String> {
+        Ok(MachineBuilder {
+            sections: parse(text)?,
+            base_path: PathBuf::new(),
+        })
+    }
+
+    // rom files are searched relative to the description
+    pub fn from_filename<P: AsRef<Path>>(filename: P) -> Result<MachineBuilder, String> {
+        let path = filename.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => return Err(format!("unable to read {}: {}", path.display(), err)),
+        };
+        let mut builder = MachineBuilder::parse(&text)?;
+        if let Some(parent) = path.parent() {
+            builder.base_path = parent.to_path_buf();
+        }
+        Ok(builder)
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn build(&self) -> Result<Machine, String> {
+        let machine_section = match self
+            .sections
+            .iter()
+            .find(|section| section.name == "machine")
+        {
+            Some(section) => section,
+            None => return Err("missing [machine] section".to_string()),
+        };
+
+        match machine_section.get("cpu") {
+            Some("mos6502") | None => (),
+            Some(cpu) => return Err(format!("[machine] unsupported cpu {}", cpu)),
+        }
+
+        let hz: u32 = machine_section.number("hz")?.unwrap_or(1_000_000);
+
+        let mut memory_controller = MemoryControllerShared::new();
+        memory_controller.unmapped_read = match machine_section.get("unmapped") {
+            Some("zero") | None => UnmappedRead::Zero,
+            Some("open_bus") => UnmappedRead::OpenBus,
+            Some("fault") => UnmappedRead::Fault,
+            Some(_) => UnmappedRead::Pattern(machine_section.required("unmapped")?),
+        };
+
+        let mut interrupts = Vec::new();
+        let mut devices: Vec<Rc<RefCell<dyn Clock>>> = Vec::new();
+        let mut irq_lines = Vec::new();
+        let mut nmi = Vec::new();
+        let mut bus_requests = Vec::new();
+        // what the dma engines see: every device but themselves
+        let dma_bus = Rc::new(RefCell::new(MemoryControllerShared::new()));
+
+        for section in &self.sections {
+            if section.name == "machine" {
+                continue;
+            }
+            let kind = match section.get("type") {
+                Some(kind) => kind,
+                None => return Err(format!("[{}] missing type", section.name)),
+            };
+
+            if kind == "interrupt" {
+                let interrupt_hz: u32 = section.required("hz")?;
+                if interrupt_hz == 0 || interrupt_hz > hz {
+                    return Err(format!("[{}] invalid hz {}", section.name, interrupt_hz));
+                }
+                let period = u64::from(hz / interrupt_hz);
+                interrupts.push(PeriodicInterrupt {
+                    line: section.required("line")?,
+                    period,
+                    next: period,
+                });
+                continue;
+            }
+
+            let start: u16 = section.required("start")?;
+            // the size of a rom defaults to the size of its file
+            let mut size: Option<u32> = section.number("size")?;
+            // the output of the devices that can interrupt the cpu
+            let mut interrupt: Option<Line> = None;
+            let device: Rc<RefCell<dyn AddressBusIO<u16, u8>>> = match kind {
+                "ram" => match size {
+                    Some(ram_size) => Rc::new(RefCell::new(Ram::new(ram_size as usize))),
+                    None => return Err(format!("[{}] missing size", section.name)),
+                },
+                "rom" => {
+                    let filename = match section.get("file") {
+                        Some(filename) => self.base_path.join(filename),
+                        None => return Err(format!("[{}] missing file", section.name)),
+                    };
+                    let data = match fs::read(&filename) {
+                        Ok(data) => data,
+                        Err(err) => {
+                            return Err(format!(
+                                "[{}] unable to read {}: {}",
+                                section.name,
+                                filename.display(),
+                                err
+                            ))
+                        }
+                    };
+                    if size.is_none() {
+                        size = Some(data.len() as u32);
+                    }
+                    Rc::new(RefCell::new(Rom::new(data)))
+                }
+                "unixterm" => Rc::new(RefCell::new(BusAdapterBoxed::new(
+                    Box::new(UnixTerm::new()),
+                ))),
+                "random" => Rc::new(RefCell::new(Random::new())),
+                "piano" => Rc::new(RefCell::new(Piano::new(
+                    section.number("duration")?.unwrap_or(125),
+                ))),
+                // the memory dma of aivmachine, its registers take 15 bytes
+                "dma" => {
+                    let bus: Rc<RefCell<dyn AddressBusIO<u16, u8>>> = dma_bus.clone();
+                    let dma = Rc::new(RefCell::new(DmaMemory::new(bus)));
+                    bus_requests.push(dma.borrow().bus_request());
+                    interrupt = Some(dma.borrow().irq());
+                    devices.push(dma.clone());
+                    if size.is_none() {
+                        size = Some(0x0f);
+                    }
+                    dma
+                }
+                _ => return Err(format!("[{}] unknown type {}", section.name, kind)),
+            };
+
+            let end = match section.number("end")? {
+                Some(end) => end,
+                None => match size {
+                    Some(size) => match size
+                        .checked_sub(1)
+                        .and_then(|last| u32::from(start).checked_add(last))
+                    {
+                        Some(end) if end <= 0xffff => end as u16,
+                        Some(_) => {
+                            return Err(format!("[{}] size exceeds address space", section.name))
+                        }
+                        None => return Err(format!("[{}] invalid size {}", section.name, size)),
+                    },
+                    None => start,
+                },
+            };
+            if end < start {
+                return Err(format!("[{}] end before start", section.name));
+            }
+
+            match (section.get("irq"), interrupt) {
+                (None, _) => (),
+                (Some("irq"), Some(line)) => irq_lines.push(line),
+                (Some("nmi"), Some(line)) => nmi.push(EdgeInterrupt { line, level: false }),
+                (Some(irq), Some(_)) => {
+                    return Err(format!("[{}] invalid irq {}", section.name, irq))
+                }
+                (Some(_), None) => {
+                    return Err(format!("[{}] {} can not interrupt", section.name, kind))
+                }
+            }
+
+            if kind != "dma" {
+                dma_bus.borrow_mut().map(start, end, device.clone());
+            }
+            let handle = memory_controller.map(start, end, device);
+            memory_controller.set_name(handle, &section.name);
+            if let Some(wait_states) = section.number("wait_states")? {
+                memory_controller.set_wait_states(start, end, wait_states);
+            }
+        }
+
+        let mut cpu = MOS6502::new(memory_controller);
+        for line in irq_lines {
+            cpu.connect_irq(line);
+        }
+        for line in bus_requests {
+            cpu.connect_bus_request(line);
+        }
+        // without an explicit pc, boot from the reset vector
+        cpu.pc = match machine_section.number("pc")? {
+            Some(pc) => pc,
+            None => cpu.read_u16_le(0xfffc),
+        };
+
+        Ok(Machine {
+            cpu,
+            hz,
+            interrupts,
+            devices,
+            nmi,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests;
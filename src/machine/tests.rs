@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use machine::{parse, MachineBuilder};
+use AddressBusIO;
+
+#[test]
+fn parse_sections() {
+    let sections = parse(
+        "# comment\n[machine]\ncpu = mos6502\n\n[ram]\ntype = ram\nstart = $0000\nsize = 4096\n",
+    )
+    .unwrap();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].name, "machine");
+    assert_eq!(sections[0].get("cpu"), Some("mos6502"));
+    assert_eq!(sections[1].get("start"), Some("$0000"));
+    assert_eq!(sections[1].get("file"), None);
+}
+
+#[test]
+fn parse_errors() {
+    assert!(parse("cpu = mos6502\n").is_err());
+    assert!(parse("[machine\n").is_err());
+    assert!(parse("[machine]\ncpu\n").is_err());
+}
+
+#[test]
+fn build_unknown_device() {
+    let builder = MachineBuilder::parse("[machine]\n[foo]\ntype = foo\nstart = 0\n").unwrap();
+    assert!(builder.build().is_err());
+}
+
+#[test]
+fn build_and_run() {
+    let builder = MachineBuilder::parse(
+        "[machine]\npc = $0200\n[ram]\ntype = ram\nstart = $0000\nsize = $1000\n",
+    )
+    .unwrap();
+    let mut machine = builder.build().unwrap();
+    machine.cpu.write(0x0200, 0xa9);
+    machine.cpu.write(0x0201, 0x17);
+    machine.step();
+    assert_eq!(machine.cpu.a, 0x17);
+    assert_eq!(machine.cpu.bus().mappings()[0].name, "ram");
+}
+
+#[test]
+fn periodic_interrupt() {
+    let builder = MachineBuilder::parse(
+        "[machine]\nhz = 200\npc = $0200\n[ram]\ntype = ram\nstart = $0000\nsize = $10000\n\
+         [vblank]\ntype = interrupt\nline = 6\nhz = 50\n",
+    )
+    .unwrap();
+    let mut machine = builder.build().unwrap();
+    machine.cpu.write(0xfffa, 0x00);
+    machine.cpu.write(0xfffb, 0x30);
+    // NOPs
+    for address in 0x0200..0x0210 {
+        machine.cpu.write(address, 0xea);
+    }
+    machine.step();
+    assert_eq!(machine.cpu.pc, 0x0201);
+    machine.step();
+    assert_eq!(machine.cpu.pc, 0x3000);
+}
+
+#[test]
+fn dma_nmi() {
+    let builder = MachineBuilder::parse(
+        "[machine]\npc = $0200\n[ram]\ntype = ram\nstart = $0000\nsize = $2000\n\
+         [dma]\ntype = dma\nstart = $2000\nirq = nmi\n\
+         [vectors]\ntype = ram\nstart = $F000\nsize = $1000\n",
+    )
+    .unwrap();
+    let mut machine = builder.build().unwrap();
+    machine.cpu.write(0xfffa, 0x00);
+    machine.cpu.write(0xfffb, 0x03);
+    // NOPs
+    for address in (0x0200..0x0210).chain(0x0300..0x0340) {
+        machine.cpu.write(address, 0xea);
+    }
+    // fill $1000-$1003 with $55, with an interrupt at the end
+    machine.cpu.write(0x2004, 0x00);
+    machine.cpu.write(0x2005, 0x10);
+    machine.cpu.write(0x2008, 4);
+    machine.cpu.write(0x200e, 0x55);
+    machine.cpu.write(0x200a, 0x83);
+    for _ in 0..16 {
+        machine.step();
+    }
+    assert_eq!(machine.cpu.read(0x1003), 0x55);
+    assert_eq!(machine.cpu.read(0x1004), 0x00);
+    // a single NMI, the line stays up until the status is read
+    assert!(machine.cpu.pc > 0x0300 && machine.cpu.pc < 0x0340);
+    assert_eq!(machine.cpu.sp, 0xfc);
+}
+
+#[test]
+fn invalid_irq() {
+    let device = "[machine]\n[dma]\ntype = dma\nstart = $2000\nirq = foo\n";
+    assert!(MachineBuilder::parse(device).unwrap().build().is_err());
+    let device = "[machine]\n[ram]\ntype = ram\nstart = $0000\nsize = $1000\nirq = irq\n";
+    assert!(MachineBuilder::parse(device).unwrap().build().is_err());
+}
+
+#[test]
+fn example_6502term() {
+    let builder = MachineBuilder::from_filename("examples/6502term.ini").unwrap();
+    let mut machine = builder.build().unwrap();
+    assert_eq!(machine.cpu.pc, 0x8000);
+    let rom = &machine.cpu.bus().mappings()[2];
+    assert_eq!((rom.start, rom.end), (0x8000, 0x8fff));
+    assert_eq!(
+        machine.cpu.read(0x8000),
+        include_bytes!("../../examples/foo.bin")[0]
+    );
+}
+
+#[test]
+fn invalid_sizes() {
+    for size in &["0", "$10001", "$FFFFFFFF"] {
+        let builder = MachineBuilder::parse(&format!(
+            "[machine]\n[ram]\ntype = ram\nstart = $0010\nsize = {}\n",
+            size
+        ))
+        .unwrap();
+        assert!(builder.build().is_err());
+    }
+}
+
+#[test]
+fn emulated_time() {
+    let builder = MachineBuilder::parse(
+        "[machine]\nhz = 1000\npc = $0200\n[ram]\ntype = ram\nstart = $0000\nsize = $1000\n",
+    )
+    .unwrap();
+    let mut machine = builder.build().unwrap();
+    // NOP, 2 cycles
+    machine.cpu.write(0x0200, 0xea);
+    machine.step();
+    assert_eq!(machine.emulated_time(), Duration::from_millis(2));
+}
@@ -1,13 +1,296 @@
 use std::cell::RefCell;
+use std::mem;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use {Address, AddressBusIO, Data};
+use {Address, AddressBusIO, BusError, Data};
 
-struct AddressMapping<'a, T: Address + 'a, U: Data + 'a> {
+// what the data bus returns when no device answers a read
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnmappedRead<U: Data> {
+    Zero,
+    // the last value transferred on the bus (read or written)
+    OpenBus,
+    Pattern(U),
+    Fault,
+}
+
+impl<U: Data> UnmappedRead<U> {
+    fn resolve<T: Address>(&self, address: T, last_value: U) -> Result<U, BusError> {
+        match *self {
+            UnmappedRead::Zero => Ok(U::zero()),
+            UnmappedRead::OpenBus => Ok(last_value),
+            UnmappedRead::Pattern(pattern) => Ok(pattern),
+            UnmappedRead::Fault => Err(BusError::unmapped_read(address)),
+        }
+    }
+}
+
+type Boxed<T, U> = Box<dyn AddressBusIO<T, U>>;
+type Shared<T, U> = Rc<RefCell<dyn AddressBusIO<T, U>>>;
+type ThreadSafe<T, U> = Arc<Mutex<dyn AddressBusIO<T, U> + Send + Sync>>;
+
+// abstracts the various ways a device can be attached to a controller
+trait Connection<T: Address, U: Data> {
+    fn try_read(&mut self, address: T) -> Result<U, BusError>;
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError>;
+    fn take_wait_states(&mut self) -> u64;
+    fn peek(&self, address: T) -> Option<U>;
+}
+
+impl<T: Address, U: Data> Connection<T, U> for &mut dyn AddressBusIO<T, U> {
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        (**self).try_read(address)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        (**self).try_write(address, value)
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        (**self).take_wait_states()
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        (**self).peek(address)
+    }
+}
+
+impl<T: Address, U: Data> Connection<T, U> for Boxed<T, U> {
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        (**self).try_read(address)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        (**self).try_write(address, value)
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        (**self).take_wait_states()
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        (**self).peek(address)
+    }
+}
+
+impl<T: Address, U: Data> Connection<T, U> for Shared<T, U> {
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        self.borrow_mut().try_read(address)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.borrow_mut().try_write(address, value)
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        self.borrow_mut().take_wait_states()
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        self.try_borrow().ok()?.peek(address)
+    }
+}
+
+impl<T: Address, U: Data> Connection<T, U> for ThreadSafe<T, U> {
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        self.lock().unwrap().try_read(address)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.lock().unwrap().try_write(address, value)
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        self.lock().unwrap().take_wait_states()
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        self.try_lock().ok()?.peek(address)
+    }
+}
+
+// returned by the various map() to later reference the mapping
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MappingHandle(usize);
+
+#[derive(Clone, Debug)]
+pub struct MappingInfo<T: Address> {
+    pub handle: MappingHandle,
+    pub start: T,
+    pub end: T,
+    pub name: String,
+    pub wait_states: u64,
+}
+
+struct AddressMapping<T: Address, C> {
+    handle: MappingHandle,
     start: T,
     end: T,
-    connection: &'a mut dyn AddressBusIO<T, U>,
+    name: String,
+    wait_states: u64,
+    connection: C,
+}
+
+impl<T: Address, C> AddressMapping<T, C> {
+    fn new(handle: MappingHandle, start: T, end: T, connection: C) -> AddressMapping<T, C> {
+        AddressMapping {
+            handle,
+            start,
+            end,
+            name: String::new(),
+            wait_states: 0,
+            connection,
+        }
+    }
+
+    fn contains(&self, address: T) -> bool {
+        address >= self.start && address <= self.end
+    }
+}
+
+fn read_mappings<T: Address, U: Data, C: Connection<T, U>>(
+    mappings: &mut [AddressMapping<T, C>],
+    address: T,
+    wait_states: &mut u64,
+) -> Option<Result<U, BusError>> {
+    for mapping in mappings {
+        if mapping.contains(address) {
+            let value = mapping.connection.try_read(address - mapping.start);
+            *wait_states += mapping.wait_states + mapping.connection.take_wait_states();
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn peek_mappings<T: Address, U: Data, C: Connection<T, U>>(
+    mappings: &[AddressMapping<T, C>],
+    address: T,
+) -> Option<Option<U>> {
+    mappings
+        .iter()
+        .find(|mapping| mapping.contains(address))
+        .map(|mapping| mapping.connection.peek(address - mapping.start))
+}
+
+fn write_mappings<T: Address, U: Data, C: Connection<T, U>>(
+    mappings: &mut [AddressMapping<T, C>],
+    address: T,
+    value: U,
+    wait_states: &mut u64,
+) -> Option<Result<(), BusError>> {
+    for mapping in mappings {
+        if mapping.contains(address) {
+            let result = mapping.connection.try_write(address - mapping.start, value);
+            *wait_states += mapping.wait_states + mapping.connection.take_wait_states();
+            return Some(result);
+        }
+    }
+    None
+}
+
+// wait states are applied to every mapping overlapping the range (mappings added
+// later are not affected), returns the number of updated mappings
+fn set_wait_states<T: Address, C>(
+    mappings: &mut [AddressMapping<T, C>],
+    start: T,
+    end: T,
+    wait_states: u64,
+) -> usize {
+    let mut updated = 0;
+    for mapping in mappings {
+        if mapping.start <= end && mapping.end >= start {
+            mapping.wait_states = wait_states;
+            updated += 1;
+        }
+    }
+    updated
+}
+
+fn push_mapping<T: Address, C>(
+    mappings: &mut Vec<AddressMapping<T, C>>,
+    handles: &mut usize,
+    start: T,
+    end: T,
+    connection: C,
+) -> MappingHandle {
+    let handle = MappingHandle(*handles);
+    *handles += 1;
+    mappings.push(AddressMapping::new(handle, start, end, connection));
+    handle
+}
+
+fn find_mapping<T: Address, C>(
+    mappings: &mut [AddressMapping<T, C>],
+    handle: MappingHandle,
+) -> Option<&mut AddressMapping<T, C>> {
+    mappings.iter_mut().find(|mapping| mapping.handle == handle)
+}
+
+fn unmap<T: Address, C>(mappings: &mut Vec<AddressMapping<T, C>>, handle: MappingHandle) -> bool {
+    match mappings.iter().position(|mapping| mapping.handle == handle) {
+        Some(index) => {
+            mappings.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+// moving a mapping does not change its priority
+fn remap<T: Address, C>(
+    mappings: &mut [AddressMapping<T, C>],
+    handle: MappingHandle,
+    start: T,
+    end: T,
+) -> bool {
+    match find_mapping(mappings, handle) {
+        Some(mapping) => {
+            mapping.start = start;
+            mapping.end = end;
+            true
+        }
+        None => false,
+    }
+}
+
+// returns the previously attached device
+fn swap<T: Address, C>(
+    mappings: &mut [AddressMapping<T, C>],
+    handle: MappingHandle,
+    connection: C,
+) -> Option<C> {
+    match find_mapping(mappings, handle) {
+        Some(mapping) => Some(mem::replace(&mut mapping.connection, connection)),
+        None => None,
+    }
+}
+
+fn set_name<T: Address, C>(
+    mappings: &mut [AddressMapping<T, C>],
+    handle: MappingHandle,
+    name: &str,
+) -> bool {
+    match find_mapping(mappings, handle) {
+        Some(mapping) => {
+            mapping.name = name.to_string();
+            true
+        }
+        None => false,
+    }
+}
+
+fn describe<T: Address, C>(mappings: &[AddressMapping<T, C>], infos: &mut Vec<MappingInfo<T>>) {
+    for mapping in mappings {
+        infos.push(MappingInfo {
+            handle: mapping.handle,
+            start: mapping.start,
+            end: mapping.end,
+            name: mapping.name.clone(),
+            wait_states: mapping.wait_states,
+        });
+    }
 }
 
 struct MirrorMapping<T: Address> {
@@ -17,9 +300,13 @@ struct MirrorMapping<T: Address> {
 }
 
 pub struct MemoryController<'a, T: Address + 'a, U: Data + 'a> {
-    mappings: Vec<AddressMapping<'a, T, U>>,
+    mappings: Vec<AddressMapping<T, &'a mut dyn AddressBusIO<T, U>>>,
     mirrors: Vec<MirrorMapping<T>>,
     pub panic_on_no_map: bool,
+    pub unmapped_read: UnmappedRead<U>,
+    last_value: U,
+    wait_states: u64,
+    handles: usize,
 }
 
 impl<'a, T: Address, U: Data> MemoryController<'a, T, U> {
@@ -28,178 +315,360 @@ impl<'a, T: Address, U: Data> MemoryController<'a, T, U> {
             mappings: Vec::new(),
             mirrors: Vec::new(),
             panic_on_no_map: false,
+            unmapped_read: UnmappedRead::Zero,
+            last_value: U::zero(),
+            wait_states: 0,
+            handles: 0,
         }
     }
 
-    pub fn map(&mut self, start: T, end: T, connection: &'a mut dyn AddressBusIO<T, U>) {
-        self.mappings.push(AddressMapping {
-            start: start,
-            end: end,
-            connection: connection,
-        });
+    pub fn map(
+        &mut self,
+        start: T,
+        end: T,
+        connection: &'a mut dyn AddressBusIO<T, U>,
+    ) -> MappingHandle {
+        push_mapping(
+            &mut self.mappings,
+            &mut self.handles,
+            start,
+            end,
+            connection,
+        )
     }
 
     pub fn mirror(&mut self, start: T, end: T, mirror: T) {
         self.mirrors.push(MirrorMapping { start, end, mirror });
     }
-}
 
-impl<'a, T: Address, U: Data> AddressBusIO<T, U> for MemoryController<'a, T, U> {
-    fn read(&mut self, address: T) -> U {
-        let mut cleaned_address = address;
-        // first check for mirrors
+    fn clean_address(&self, address: T) -> T {
         for mirror in &self.mirrors {
             if address >= mirror.start && address <= mirror.end {
-                cleaned_address = mirror.mirror + address - mirror.start;
-                break;
-            }
-        }
-        for mapping in &mut self.mappings {
-            if cleaned_address >= mapping.start && cleaned_address <= mapping.end {
-                return mapping.connection.read(cleaned_address - mapping.start);
+                return mirror.mirror + address - mirror.start;
             }
         }
-        if self.panic_on_no_map {
-            panic!("unknown mapping ${:X}", address);
+        address
+    }
+
+    pub fn set_wait_states(&mut self, start: T, end: T, wait_states: u64) -> usize {
+        set_wait_states(&mut self.mappings, start, end, wait_states)
+    }
+
+    pub fn unmap(&mut self, handle: MappingHandle) -> bool {
+        unmap(&mut self.mappings, handle)
+    }
+
+    pub fn remap(&mut self, handle: MappingHandle, start: T, end: T) -> bool {
+        remap(&mut self.mappings, handle, start, end)
+    }
+
+    pub fn swap(
+        &mut self,
+        handle: MappingHandle,
+        connection: &'a mut dyn AddressBusIO<T, U>,
+    ) -> Option<&'a mut dyn AddressBusIO<T, U>> {
+        swap(&mut self.mappings, handle, connection)
+    }
+
+    pub fn set_name(&mut self, handle: MappingHandle, name: &str) -> bool {
+        set_name(&mut self.mappings, handle, name)
+    }
+
+    pub fn mappings(&self) -> Vec<MappingInfo<T>> {
+        let mut infos = Vec::new();
+        describe(&self.mappings, &mut infos);
+        infos
+    }
+}
+
+impl<'a, T: Address, U: Data> AddressBusIO<T, U> for MemoryController<'a, T, U> {
+    fn read(&mut self, address: T) -> U {
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
         }
-        U::zero()
     }
 
     fn write(&mut self, address: T, value: U) {
-        let mut cleaned_address = address;
-        // first check for mirrors
-        for mirror in &self.mirrors {
-            if address >= mirror.start && address <= mirror.end {
-                cleaned_address = mirror.mirror + address - mirror.start;
-                break;
-            }
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
         }
-        for mapping in &mut self.mappings {
-            if cleaned_address >= mapping.start && cleaned_address <= mapping.end {
-                mapping
-                    .connection
-                    .write(cleaned_address - mapping.start, value);
-                return;
+    }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        // first check for mirrors
+        let cleaned_address = self.clean_address(address);
+        let value = match read_mappings(&mut self.mappings, cleaned_address, &mut self.wait_states)
+        {
+            Some(result) => result?,
+            None => {
+                if self.panic_on_no_map {
+                    return Err(BusError::unmapped_read(address));
+                }
+                self.unmapped_read.resolve(address, self.last_value)?
             }
-        }
-        if self.panic_on_no_map {
-            panic!("unknown mapping ${:X}", address);
+        };
+        self.last_value = value;
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        // first check for mirrors
+        let cleaned_address = self.clean_address(address);
+        self.last_value = value;
+        match write_mappings(
+            &mut self.mappings,
+            cleaned_address,
+            value,
+            &mut self.wait_states,
+        ) {
+            Some(result) => result,
+            None if self.panic_on_no_map => Err(BusError::unmapped_write(address)),
+            None => Ok(()),
         }
     }
-}
 
-struct AddressMappingBoxed<T: Address, U: Data> {
-    start: T,
-    end: T,
-    connection: Box<dyn AddressBusIO<T, U>>,
+    fn take_wait_states(&mut self) -> u64 {
+        let wait_states = self.wait_states;
+        self.wait_states = 0;
+        wait_states
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        peek_mappings(&self.mappings, self.clean_address(address)).unwrap_or(None)
+    }
 }
 
 pub struct MemoryControllerBoxed<T: Address, U: Data> {
-    mappings: Vec<AddressMappingBoxed<T, U>>,
+    mappings: Vec<AddressMapping<T, Boxed<T, U>>>,
+    pub unmapped_read: UnmappedRead<U>,
+    last_value: U,
+    wait_states: u64,
+    handles: usize,
 }
 
 impl<T: Address, U: Data> MemoryControllerBoxed<T, U> {
     pub fn new() -> MemoryControllerBoxed<T, U> {
         MemoryControllerBoxed {
             mappings: Vec::new(),
+            unmapped_read: UnmappedRead::Zero,
+            last_value: U::zero(),
+            wait_states: 0,
+            handles: 0,
         }
     }
 
-    pub fn map(&mut self, start: T, end: T, connection: Box<dyn AddressBusIO<T, U>>) {
-        self.mappings.push(AddressMappingBoxed {
-            start: start,
-            end: end,
-            connection: connection,
-        });
+    pub fn map(
+        &mut self,
+        start: T,
+        end: T,
+        connection: Box<dyn AddressBusIO<T, U>>,
+    ) -> MappingHandle {
+        push_mapping(
+            &mut self.mappings,
+            &mut self.handles,
+            start,
+            end,
+            connection,
+        )
+    }
+
+    pub fn set_wait_states(&mut self, start: T, end: T, wait_states: u64) -> usize {
+        set_wait_states(&mut self.mappings, start, end, wait_states)
+    }
+
+    pub fn unmap(&mut self, handle: MappingHandle) -> bool {
+        unmap(&mut self.mappings, handle)
+    }
+
+    pub fn remap(&mut self, handle: MappingHandle, start: T, end: T) -> bool {
+        remap(&mut self.mappings, handle, start, end)
+    }
+
+    pub fn swap(
+        &mut self,
+        handle: MappingHandle,
+        connection: Box<dyn AddressBusIO<T, U>>,
+    ) -> Option<Box<dyn AddressBusIO<T, U>>> {
+        swap(&mut self.mappings, handle, connection)
+    }
+
+    pub fn set_name(&mut self, handle: MappingHandle, name: &str) -> bool {
+        set_name(&mut self.mappings, handle, name)
+    }
+
+    pub fn mappings(&self) -> Vec<MappingInfo<T>> {
+        let mut infos = Vec::new();
+        describe(&self.mappings, &mut infos);
+        infos
     }
 }
 
 impl<T: Address, U: Data> AddressBusIO<T, U> for MemoryControllerBoxed<T, U> {
     fn read(&mut self, address: T) -> U {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                return mapping.connection.read(address - mapping.start);
-            }
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
         }
-        U::zero()
     }
 
     fn write(&mut self, address: T, value: U) {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                mapping.connection.write(address - mapping.start, value);
-                return;
-            }
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
         }
     }
-}
 
-struct AddressMappingShared<T: Address, U: Data> {
-    start: T,
-    end: T,
-    connection: Rc<RefCell<dyn AddressBusIO<T, U>>>,
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let value = match read_mappings(&mut self.mappings, address, &mut self.wait_states) {
+            Some(result) => result?,
+            None => self.unmapped_read.resolve(address, self.last_value)?,
+        };
+        self.last_value = value;
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.last_value = value;
+        match write_mappings(&mut self.mappings, address, value, &mut self.wait_states) {
+            Some(result) => result,
+            None => Ok(()),
+        }
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        let wait_states = self.wait_states;
+        self.wait_states = 0;
+        wait_states
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        peek_mappings(&self.mappings, address).unwrap_or(None)
+    }
 }
 
 pub struct MemoryControllerShared<T: Address, U: Data> {
-    mappings: Vec<AddressMappingShared<T, U>>,
+    mappings: Vec<AddressMapping<T, Shared<T, U>>>,
+    pub unmapped_read: UnmappedRead<U>,
+    last_value: U,
+    wait_states: u64,
+    handles: usize,
 }
 
 impl<T: Address, U: Data> MemoryControllerShared<T, U> {
     pub fn new() -> MemoryControllerShared<T, U> {
         MemoryControllerShared {
             mappings: Vec::new(),
+            unmapped_read: UnmappedRead::Zero,
+            last_value: U::zero(),
+            wait_states: 0,
+            handles: 0,
         }
     }
 
-    pub fn map(&mut self, start: T, end: T, connection: Rc<RefCell<dyn AddressBusIO<T, U>>>) {
-        self.mappings.push(AddressMappingShared {
-            start: start,
-            end: end,
-            connection: connection,
-        });
+    pub fn map(
+        &mut self,
+        start: T,
+        end: T,
+        connection: Rc<RefCell<dyn AddressBusIO<T, U>>>,
+    ) -> MappingHandle {
+        push_mapping(
+            &mut self.mappings,
+            &mut self.handles,
+            start,
+            end,
+            connection,
+        )
+    }
+
+    pub fn set_wait_states(&mut self, start: T, end: T, wait_states: u64) -> usize {
+        set_wait_states(&mut self.mappings, start, end, wait_states)
+    }
+
+    pub fn unmap(&mut self, handle: MappingHandle) -> bool {
+        unmap(&mut self.mappings, handle)
+    }
+
+    pub fn remap(&mut self, handle: MappingHandle, start: T, end: T) -> bool {
+        remap(&mut self.mappings, handle, start, end)
+    }
+
+    pub fn swap(
+        &mut self,
+        handle: MappingHandle,
+        connection: Rc<RefCell<dyn AddressBusIO<T, U>>>,
+    ) -> Option<Rc<RefCell<dyn AddressBusIO<T, U>>>> {
+        swap(&mut self.mappings, handle, connection)
+    }
+
+    pub fn set_name(&mut self, handle: MappingHandle, name: &str) -> bool {
+        set_name(&mut self.mappings, handle, name)
+    }
+
+    pub fn mappings(&self) -> Vec<MappingInfo<T>> {
+        let mut infos = Vec::new();
+        describe(&self.mappings, &mut infos);
+        infos
     }
 }
 
 impl<T: Address, U: Data> AddressBusIO<T, U> for MemoryControllerShared<T, U> {
     fn read(&mut self, address: T) -> U {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                return mapping
-                    .connection
-                    .borrow_mut()
-                    .read(address - mapping.start);
-            }
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
         }
-        U::zero()
     }
 
     fn write(&mut self, address: T, value: U) {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                mapping
-                    .connection
-                    .borrow_mut()
-                    .write(address - mapping.start, value);
-                return;
-            }
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
         }
     }
-}
 
-struct AddressMappingThreadSafe<T: Address, U: Data> {
-    start: T,
-    end: T,
-    connection: Arc<Mutex<dyn AddressBusIO<T, U> + Send + Sync>>,
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let value = match read_mappings(&mut self.mappings, address, &mut self.wait_states) {
+            Some(result) => result?,
+            None => self.unmapped_read.resolve(address, self.last_value)?,
+        };
+        self.last_value = value;
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.last_value = value;
+        match write_mappings(&mut self.mappings, address, value, &mut self.wait_states) {
+            Some(result) => result,
+            None => Ok(()),
+        }
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        let wait_states = self.wait_states;
+        self.wait_states = 0;
+        wait_states
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        peek_mappings(&self.mappings, address).unwrap_or(None)
+    }
 }
 
 pub struct MemoryControllerThreadSafe<T: Address, U: Data> {
-    mappings: Vec<AddressMappingThreadSafe<T, U>>,
+    mappings: Vec<AddressMapping<T, ThreadSafe<T, U>>>,
+    pub unmapped_read: UnmappedRead<U>,
+    last_value: U,
+    wait_states: u64,
+    handles: usize,
 }
 
 impl<T: Address, U: Data> MemoryControllerThreadSafe<T, U> {
     pub fn new() -> MemoryControllerThreadSafe<T, U> {
         MemoryControllerThreadSafe {
             mappings: Vec::new(),
+            unmapped_read: UnmappedRead::Zero,
+            last_value: U::zero(),
+            wait_states: 0,
+            handles: 0,
         }
     }
 
@@ -208,46 +677,96 @@ impl<T: Address, U: Data> MemoryControllerThreadSafe<T, U> {
         start: T,
         end: T,
         connection: Arc<Mutex<dyn AddressBusIO<T, U> + Send + Sync>>,
-    ) {
-        self.mappings.push(AddressMappingThreadSafe {
-            start: start,
-            end: end,
-            connection: connection,
-        });
+    ) -> MappingHandle {
+        push_mapping(
+            &mut self.mappings,
+            &mut self.handles,
+            start,
+            end,
+            connection,
+        )
+    }
+
+    pub fn set_wait_states(&mut self, start: T, end: T, wait_states: u64) -> usize {
+        set_wait_states(&mut self.mappings, start, end, wait_states)
+    }
+
+    pub fn unmap(&mut self, handle: MappingHandle) -> bool {
+        unmap(&mut self.mappings, handle)
+    }
+
+    pub fn remap(&mut self, handle: MappingHandle, start: T, end: T) -> bool {
+        remap(&mut self.mappings, handle, start, end)
+    }
+
+    pub fn swap(
+        &mut self,
+        handle: MappingHandle,
+        connection: Arc<Mutex<dyn AddressBusIO<T, U> + Send + Sync>>,
+    ) -> Option<Arc<Mutex<dyn AddressBusIO<T, U> + Send + Sync>>> {
+        swap(&mut self.mappings, handle, connection)
+    }
+
+    pub fn set_name(&mut self, handle: MappingHandle, name: &str) -> bool {
+        set_name(&mut self.mappings, handle, name)
+    }
+
+    pub fn mappings(&self) -> Vec<MappingInfo<T>> {
+        let mut infos = Vec::new();
+        describe(&self.mappings, &mut infos);
+        infos
     }
 }
 
 impl<T: Address, U: Data> AddressBusIO<T, U> for MemoryControllerThreadSafe<T, U> {
     fn read(&mut self, address: T) -> U {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                return mapping
-                    .connection
-                    .lock()
-                    .unwrap()
-                    .read(address - mapping.start);
-            }
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
         }
-        U::zero()
     }
 
     fn write(&mut self, address: T, value: U) {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                mapping
-                    .connection
-                    .lock()
-                    .unwrap()
-                    .write(address - mapping.start, value);
-                return;
-            }
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
         }
     }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let value = match read_mappings(&mut self.mappings, address, &mut self.wait_states) {
+            Some(result) => result?,
+            None => self.unmapped_read.resolve(address, self.last_value)?,
+        };
+        self.last_value = value;
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.last_value = value;
+        match write_mappings(&mut self.mappings, address, value, &mut self.wait_states) {
+            Some(result) => result,
+            None => Ok(()),
+        }
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        let wait_states = self.wait_states;
+        self.wait_states = 0;
+        wait_states
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        peek_mappings(&self.mappings, address).unwrap_or(None)
+    }
 }
 
 pub struct MemoryControllerSmart<'a, T: Address + 'a, U: Data + 'a> {
-    mappings: Vec<AddressMapping<'a, T, U>>,
-    shared_mappings: Vec<AddressMappingShared<T, U>>,
+    mappings: Vec<AddressMapping<T, &'a mut dyn AddressBusIO<T, U>>>,
+    shared_mappings: Vec<AddressMapping<T, Shared<T, U>>>,
+    pub unmapped_read: UnmappedRead<U>,
+    last_value: U,
+    wait_states: u64,
+    handles: usize,
 }
 
 impl<'a, T: Address, U: Data> MemoryControllerSmart<'a, T, U> {
@@ -255,14 +774,25 @@ impl<'a, T: Address, U: Data> MemoryControllerSmart<'a, T, U> {
         MemoryControllerSmart {
             mappings: Vec::new(),
             shared_mappings: Vec::new(),
+            unmapped_read: UnmappedRead::Zero,
+            last_value: U::zero(),
+            wait_states: 0,
+            handles: 0,
         }
     }
-    pub fn map(&mut self, start: T, end: T, connection: &'a mut dyn AddressBusIO<T, U>) {
-        self.mappings.push(AddressMapping {
-            start: start,
-            end: end,
-            connection: connection,
-        });
+    pub fn map(
+        &mut self,
+        start: T,
+        end: T,
+        connection: &'a mut dyn AddressBusIO<T, U>,
+    ) -> MappingHandle {
+        push_mapping(
+            &mut self.mappings,
+            &mut self.handles,
+            start,
+            end,
+            connection,
+        )
     }
 
     pub fn map_shared(
@@ -270,48 +800,119 @@ impl<'a, T: Address, U: Data> MemoryControllerSmart<'a, T, U> {
         start: T,
         end: T,
         connection: Rc<RefCell<dyn AddressBusIO<T, U>>>,
-    ) {
-        self.shared_mappings.push(AddressMappingShared {
-            start: start,
-            end: end,
-            connection: connection,
-        });
+    ) -> MappingHandle {
+        push_mapping(
+            &mut self.shared_mappings,
+            &mut self.handles,
+            start,
+            end,
+            connection,
+        )
+    }
+
+    pub fn set_wait_states(&mut self, start: T, end: T, wait_states: u64) -> usize {
+        set_wait_states(&mut self.mappings, start, end, wait_states)
+            + set_wait_states(&mut self.shared_mappings, start, end, wait_states)
+    }
+
+    // handles are unique across plain and shared mappings
+    pub fn unmap(&mut self, handle: MappingHandle) -> bool {
+        unmap(&mut self.mappings, handle) || unmap(&mut self.shared_mappings, handle)
+    }
+
+    pub fn remap(&mut self, handle: MappingHandle, start: T, end: T) -> bool {
+        remap(&mut self.mappings, handle, start, end)
+            || remap(&mut self.shared_mappings, handle, start, end)
+    }
+
+    pub fn swap(
+        &mut self,
+        handle: MappingHandle,
+        connection: &'a mut dyn AddressBusIO<T, U>,
+    ) -> Option<&'a mut dyn AddressBusIO<T, U>> {
+        swap(&mut self.mappings, handle, connection)
+    }
+
+    pub fn swap_shared(
+        &mut self,
+        handle: MappingHandle,
+        connection: Rc<RefCell<dyn AddressBusIO<T, U>>>,
+    ) -> Option<Rc<RefCell<dyn AddressBusIO<T, U>>>> {
+        swap(&mut self.shared_mappings, handle, connection)
+    }
+
+    pub fn set_name(&mut self, handle: MappingHandle, name: &str) -> bool {
+        set_name(&mut self.mappings, handle, name)
+            || set_name(&mut self.shared_mappings, handle, name)
+    }
+
+    pub fn mappings(&self) -> Vec<MappingInfo<T>> {
+        let mut infos = Vec::new();
+        describe(&self.mappings, &mut infos);
+        describe(&self.shared_mappings, &mut infos);
+        infos
     }
 }
 
 impl<'a, T: Address, U: Data> AddressBusIO<T, U> for MemoryControllerSmart<'a, T, U> {
     fn read(&mut self, address: T) -> U {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                return mapping.connection.read(address - mapping.start);
-            }
-        }
-        for mapping in &mut self.shared_mappings {
-            if address >= mapping.start && address <= mapping.end {
-                return mapping
-                    .connection
-                    .borrow_mut()
-                    .read(address - mapping.start);
-            }
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
         }
-        U::zero()
     }
 
     fn write(&mut self, address: T, value: U) {
-        for mapping in &mut self.mappings {
-            if address >= mapping.start && address <= mapping.end {
-                mapping.connection.write(address - mapping.start, value);
-                return;
-            }
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
         }
-        for mapping in &mut self.shared_mappings {
-            if address >= mapping.start && address <= mapping.end {
-                mapping
-                    .connection
-                    .borrow_mut()
-                    .write(address - mapping.start, value);
-                return;
+    }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let value = match read_mappings(&mut self.mappings, address, &mut self.wait_states) {
+            Some(result) => result?,
+            None => {
+                match read_mappings(&mut self.shared_mappings, address, &mut self.wait_states) {
+                    Some(result) => result?,
+                    None => self.unmapped_read.resolve(address, self.last_value)?,
+                }
             }
+        };
+        self.last_value = value;
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.last_value = value;
+        if let Some(result) =
+            write_mappings(&mut self.mappings, address, value, &mut self.wait_states)
+        {
+            return result;
+        }
+        match write_mappings(
+            &mut self.shared_mappings,
+            address,
+            value,
+            &mut self.wait_states,
+        ) {
+            Some(result) => result,
+            None => Ok(()),
+        }
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        let wait_states = self.wait_states;
+        self.wait_states = 0;
+        wait_states
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        match peek_mappings(&self.mappings, address) {
+            Some(value) => value,
+            None => peek_mappings(&self.shared_mappings, address).unwrap_or(None),
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,135 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use memcontroller::{MemoryController, MemoryControllerBoxed, MemoryControllerSmart, UnmappedRead};
+use ram::Ram;
+use {AddressBusIO, BusError};
+
+#[test]
+fn unmapped_read_zero() {
+    let mut ram = Ram::new(16);
+    let mut memory_controller = MemoryController::new();
+    memory_controller.map(0x0000, 0x000f, &mut ram);
+    memory_controller.write(0x0001u16, 0x17u8);
+    assert_eq!(memory_controller.read(0x1000), 0);
+}
+
+#[test]
+fn unmapped_read_open_bus() {
+    let mut ram = Ram::new(16);
+    ram.fill(vec![0x22], 0);
+    let mut memory_controller = MemoryController::new();
+    memory_controller.unmapped_read = UnmappedRead::OpenBus;
+    memory_controller.map(0x0000, 0x000f, &mut ram);
+    memory_controller.write(0x0001u16, 0x17u8);
+    assert_eq!(memory_controller.read(0x1000), 0x17);
+    assert_eq!(memory_controller.read(0x0000), 0x22);
+    assert_eq!(memory_controller.read(0x1000), 0x22);
+}
+
+#[test]
+fn unmapped_read_pattern() {
+    let mut memory_controller: MemoryControllerBoxed<u16, u8> = MemoryControllerBoxed::new();
+    memory_controller.unmapped_read = UnmappedRead::Pattern(0xea);
+    memory_controller.map(0x0000, 0x000f, Box::new(Ram::new(16)));
+    assert_eq!(memory_controller.read(0x0000), 0x00);
+    assert_eq!(memory_controller.read(0x8000), 0xea);
+}
+
+#[test]
+#[should_panic(expected = "unmapped read at $8000")]
+fn unmapped_read_fault() {
+    let mut memory_controller: MemoryControllerBoxed<u16, u8> = MemoryControllerBoxed::new();
+    memory_controller.unmapped_read = UnmappedRead::Fault;
+    memory_controller.read(0x8000);
+}
+
+#[test]
+fn try_read_fault() {
+    let mut memory_controller: MemoryControllerBoxed<u16, u8> = MemoryControllerBoxed::new();
+    memory_controller.unmapped_read = UnmappedRead::Fault;
+    memory_controller.map(0x0000, 0x000f, Box::new(Ram::new(8)));
+    match memory_controller.try_read(0x8000) {
+        Err(BusError::UnmappedRead(address)) => assert_eq!(address, 0x8000),
+        _ => panic!("expected an unmapped read"),
+    }
+    // the mapping is wider than the ram behind it
+    match memory_controller.try_write(0x000c, 0x17) {
+        Err(BusError::OutOfRange(address)) => assert_eq!(address, 0x000c),
+        _ => panic!("expected an out of range write"),
+    }
+    assert!(memory_controller.try_write(0x8000, 0x17).is_ok());
+}
+
+#[test]
+fn wait_states() {
+    let mut ram = Ram::new(16);
+    let mut rom = Ram::new(16);
+    let mut memory_controller = MemoryController::new();
+    memory_controller.map(0x0000, 0x000f, &mut ram);
+    memory_controller.map(0x8000, 0x800f, &mut rom);
+    memory_controller.set_wait_states(0x8000, 0xffff, 2);
+    memory_controller.write(0x0001u16, 0x17u8);
+    assert_eq!(memory_controller.take_wait_states(), 0);
+    memory_controller.read(0x8000);
+    memory_controller.write(0x8001, 0x17);
+    assert_eq!(memory_controller.take_wait_states(), 4);
+    assert_eq!(memory_controller.take_wait_states(), 0);
+}
+
+#[test]
+fn wait_states_on_overlapping_mappings() {
+    let mut ram: Ram<u8> = Ram::new(16);
+    let mut rom: Ram<u8> = Ram::new(16);
+    let mut memory_controller = MemoryController::new();
+    memory_controller.map(0x0000, 0x000f, &mut ram);
+    memory_controller.map(0x8000, 0x800f, &mut rom);
+    assert_eq!(memory_controller.set_wait_states(0x0008, 0x8003, 1), 2);
+    assert_eq!(memory_controller.set_wait_states(0x1000, 0x1fff, 1), 0);
+    assert_eq!(memory_controller.read(0x0000u16), 0x00u8);
+    memory_controller.read(0x800f);
+    assert_eq!(memory_controller.take_wait_states(), 2);
+}
+
+#[test]
+fn unmap_and_remap() {
+    let mut ram = Ram::new(16);
+    ram.fill(vec![0x22], 0);
+    let mut memory_controller = MemoryController::new();
+    let handle = memory_controller.map(0x0000, 0x000f, &mut ram);
+    assert_eq!(memory_controller.read(0x0000u16), 0x22u8);
+    assert!(memory_controller.remap(handle, 0x1000, 0x100f));
+    assert_eq!(memory_controller.read(0x0000), 0);
+    assert_eq!(memory_controller.read(0x1000), 0x22);
+    assert!(memory_controller.unmap(handle));
+    assert_eq!(memory_controller.read(0x1000), 0);
+    assert!(!memory_controller.unmap(handle));
+}
+
+#[test]
+fn swap_device() {
+    let mut cartridge0 = Ram::new(16);
+    let mut cartridge1 = Ram::new(16);
+    cartridge0.fill(vec![0x01], 0);
+    cartridge1.fill(vec![0x02], 0);
+    let mut memory_controller = MemoryController::new();
+    let handle = memory_controller.map(0x8000, 0x800f, &mut cartridge0);
+    assert_eq!(memory_controller.read(0x8000u16), 0x01u8);
+    assert!(memory_controller.swap(handle, &mut cartridge1).is_some());
+    assert_eq!(memory_controller.read(0x8000), 0x02);
+}
+
+#[test]
+fn list_mappings() {
+    let mut memory_controller: MemoryControllerSmart<u16, u8> = MemoryControllerSmart::new();
+    let ram = memory_controller.map_shared(0x0000, 0x0fff, Rc::new(RefCell::new(Ram::new(4096))));
+    memory_controller.set_name(ram, "ram");
+    memory_controller.set_wait_states(0x0000, 0x0fff, 1);
+    let mappings = memory_controller.mappings();
+    assert_eq!(mappings.len(), 1);
+    assert_eq!(mappings[0].handle, ram);
+    assert_eq!(mappings[0].start, 0x0000);
+    assert_eq!(mappings[0].end, 0x0fff);
+    assert_eq!(mappings[0].name, "ram");
+    assert_eq!(mappings[0].wait_states, 1);
+}
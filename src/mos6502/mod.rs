@@ -1,4 +1,9 @@
-use {AddressBusIO, Clock, Debug, Interrupt};
+use offset_address;
+use symbols::SymbolTable;
+use {
+    AddressBusExt, AddressBusIO, BusError, Clock, Debug, InstructionKind, Interrupt, Line,
+    RegisterInfo,
+};
 
 const CARRY: u8 = 0x01;
 const ZERO: u8 = 0x02;
@@ -37,9 +42,19 @@ pub struct MOS6502<T: AddressBusIO<u16, u8>> {
     pub debug: bool,
     pub debug_line: String,
     pub debug_pc: u16,
+    // operands matching a symbol are shown by name in debug_line
+    pub symbols: SymbolTable,
 
     pub ticks: u64,
 
+    // first failed bus access, the cpu halts until it is cleared
+    pub trap: Option<BusError>,
+
+    // RDY: while any bus master holds its line the cpu waits
+    bus_request: Vec<Line>,
+    // level triggered IRQ, each device releases its line once acknowledged
+    irq: Vec<Line>,
+
     value: u8,
     addr: u16,
 
@@ -79,11 +94,15 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
             value: 0,
             addr: 0,
             ticks: 0,
+            trap: None,
+            bus_request: Vec::new(),
+            irq: Vec::new(),
             opcode: noop,
             current_opcode: 0,
 
             debug_pc: 0,
             debug_line: "".to_string(),
+            symbols: SymbolTable::new(),
 
             opcodes: [noop; 256],
 
@@ -222,6 +241,11 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         cpu
     }
 
+    // allows remapping devices while the machine runs
+    pub fn bus(&mut self) -> &mut T {
+        &mut self.bus
+    }
+
     fn register_opcode(
         &mut self,
         name: &'static str,
@@ -237,17 +261,48 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
     }
 
     fn read8(&mut self, addr: u16) -> u8 {
-        self.bus.read(addr)
+        match self.bus.try_read(addr) {
+            Ok(value) => value,
+            Err(err) => {
+                self.raise_trap(err);
+                0
+            }
+        }
+    }
+
+    fn raise_trap(&mut self, err: BusError) {
+        if self.trap.is_none() {
+            self.trap = Some(err);
+        }
+    }
+
+    pub fn connect_bus_request(&mut self, line: Line) {
+        self.bus_request.push(line);
+    }
+
+    pub fn connect_irq(&mut self, line: Line) {
+        self.irq.push(line);
+    }
+
+    // the next step is stolen by a bus master and fetches no instruction
+    pub fn is_stalled(&self) -> bool {
+        self.bus_request.iter().any(Line::is_set)
     }
 
     fn read16(&mut self, addr: u16) -> u16 {
-        let low = u16::from(self.read8(addr));
-        let high = u16::from(self.read8(addr + 1));
-        (high << 8) | low
+        match self.bus.try_read_u16_le(addr) {
+            Ok(value) => value,
+            Err(err) => {
+                self.raise_trap(err);
+                0
+            }
+        }
     }
 
     fn write8(&mut self, addr: u16, value: u8) {
-        self.bus.write(addr, value)
+        if let Err(err) = self.bus.try_write(addr, value) {
+            self.raise_trap(err);
+        }
     }
 
     fn read8_from_pc(&mut self) -> u8 {
@@ -256,14 +311,14 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
     }
 
     fn read16_from_pc(&mut self) -> u16 {
-        let low = u16::from(self.read8_from_pc());
-        let high = u16::from(self.read8_from_pc());
-        (high << 8) | low
+        let pc = self.pc;
+        self.pc = offset_address(pc, 2);
+        self.read16(pc)
     }
 
     fn advance_pc(&mut self) -> u16 {
         let pc = self.pc;
-        self.pc += 1;
+        self.pc = offset_address(pc, 1);
         pc
     }
 
@@ -271,6 +326,14 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.opcode.name
     }
 
+    fn operand_str(&self, address: u16, zeropage: bool) -> String {
+        match self.symbols.name(u32::from(address)) {
+            Some(name) => name.to_string(),
+            None if zeropage => format!("${:02X}", address),
+            None => format!("${:04X}", address),
+        }
+    }
+
     fn implied(&mut self) {
         self.ticks += 2;
         if self.debug {
@@ -299,7 +362,11 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         let addr = i32::from(self.pc) + i32::from(offset);
         self.addr = addr as u16;
         if self.debug {
-            self.debug_line = format!("{} ${:04X}", self.get_opcode_name(), self.addr);
+            self.debug_line = format!(
+                "{} {}",
+                self.get_opcode_name(),
+                self.operand_str(self.addr, false)
+            );
         }
     }
 
@@ -308,7 +375,13 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.addr = addr;
         self.value = self.read8(addr);
         self.ticks += 3;
-        self.debug_line = format!("{} ${:02X}", self.get_opcode_name(), self.addr);
+        if self.debug {
+            self.debug_line = format!(
+                "{} {}",
+                self.get_opcode_name(),
+                self.operand_str(self.addr, true)
+            );
+        }
     }
 
     fn absolute(&mut self) {
@@ -317,7 +390,11 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.value = self.read8(addr);
         self.ticks += 4;
         if self.debug {
-            self.debug_line = format!("{} ${:04X}", self.get_opcode_name(), self.addr);
+            self.debug_line = format!(
+                "{} {}",
+                self.get_opcode_name(),
+                self.operand_str(self.addr, false)
+            );
         }
     }
 
@@ -334,9 +411,9 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.ticks += 4 + boundary;
         if self.debug {
             self.debug_line = format!(
-                "{} ${:04X},X (absolute addr: ${:04X})",
+                "{} {},X (absolute addr: ${:04X})",
                 self.get_opcode_name(),
-                original_addr,
+                self.operand_str(original_addr, false),
                 self.addr
             );
         }
@@ -355,9 +432,9 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.ticks += 4 + boundary;
         if self.debug {
             self.debug_line = format!(
-                "{} ${:04X},Y (absolute addr: ${:04X})",
+                "{} {},Y (absolute addr: ${:04X})",
                 self.get_opcode_name(),
-                original_addr,
+                self.operand_str(original_addr, false),
                 self.addr
             );
         }
@@ -372,9 +449,9 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.ticks += 3;
         if self.debug {
             self.debug_line = format!(
-                "{} ${:02X},X (zeropage addr: ${:02X})",
+                "{} {},X (zeropage addr: ${:02X})",
                 self.get_opcode_name(),
-                original_addr,
+                self.operand_str(u16::from(original_addr), true),
                 self.addr
             );
         }
@@ -389,9 +466,9 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.ticks += 3;
         if self.debug {
             self.debug_line = format!(
-                "{} ${:02X},Y (zeropage addr: ${:02X})",
+                "{} {},Y (zeropage addr: ${:02X})",
                 self.get_opcode_name(),
-                original_addr,
+                self.operand_str(u16::from(original_addr), true),
                 self.addr
             );
         }
@@ -406,9 +483,9 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.ticks += 2;
         if self.debug {
             self.debug_line = format!(
-                "{} (${:04X}) (indirect addr: ${:04X})",
+                "{} ({}) (indirect addr: ${:04X})",
                 self.get_opcode_name(),
-                addr,
+                self.operand_str(addr, false),
                 self.addr
             );
         }
@@ -426,9 +503,9 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         self.ticks += 3;
         if self.debug {
             self.debug_line = format!(
-                "{} (${:02X},X) (indirect addr: ${:04X})",
+                "{} ({},X) (indirect addr: ${:04X})",
                 self.get_opcode_name(),
-                original_offset,
+                self.operand_str(original_offset, true),
                 self.addr
             );
         }
@@ -448,9 +525,9 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
         }
         if self.debug {
             self.debug_line = format!(
-                "{} (${:02X}),Y (indirect addr: ${:04X})",
+                "{} ({}),Y (indirect addr: ${:04X})",
                 self.get_opcode_name(),
-                offset,
+                self.operand_str(offset, true),
                 self.addr
             );
         }
@@ -962,6 +1039,17 @@ impl<T: AddressBusIO<u16, u8>> MOS6502<T> {
 
 impl<T: AddressBusIO<u16, u8>> Clock for MOS6502<T> {
     fn step(&mut self) {
+        if self.trap.is_some() {
+            return;
+        }
+        if self.is_stalled() {
+            // the cycle is stolen by the bus master
+            self.ticks += 1;
+            return;
+        }
+        if self.irq.iter().any(Line::is_set) {
+            self.raise(4);
+        }
         self.debug_pc = self.pc;
         let opcode = self.read8_from_pc();
         self.current_opcode = opcode;
@@ -970,6 +1058,8 @@ impl<T: AddressBusIO<u16, u8>> Clock for MOS6502<T> {
         (self.opcode.fetch)(self);
         // execute
         (self.opcode.fun)(self);
+        // slow devices stretch the instruction
+        self.ticks += self.bus.take_wait_states();
         if self.debug {
             let f_s = if self.get_flag(SIGN) { "S" } else { "-" };
             let f_v = if self.get_flag(OVERFLOW) { "V" } else { "-" };
@@ -991,6 +1081,12 @@ impl<T: AddressBusIO<u16, u8>> Clock for MOS6502<T> {
             );
         }
     }
+
+    fn tick(&mut self) -> u64 {
+        let ticks = self.ticks;
+        self.step();
+        self.ticks - ticks
+    }
 }
 
 impl<T: AddressBusIO<u16, u8>> AddressBusIO<u16, u8> for MOS6502<T> {
@@ -1001,6 +1097,14 @@ impl<T: AddressBusIO<u16, u8>> AddressBusIO<u16, u8> for MOS6502<T> {
     fn write(&mut self, address: u16, data: u8) {
         self.write8(address, data)
     }
+
+    fn try_read(&mut self, address: u16) -> Result<u8, BusError> {
+        self.bus.try_read(address)
+    }
+
+    fn try_write(&mut self, address: u16, data: u8) -> Result<(), BusError> {
+        self.bus.try_write(address, data)
+    }
 }
 
 impl<T: AddressBusIO<u16, u8>> Debug<u16, u8> for MOS6502<T> {
@@ -1012,12 +1116,17 @@ impl<T: AddressBusIO<u16, u8>> Debug<u16, u8> for MOS6502<T> {
         format!("${:02X}", data)
     }
 
+    // failed accesses read as 0 and do not trap the cpu, accesses between
+    // instructions do not stretch the next one
     fn inspect(&mut self, address: u16) -> u8 {
-        self.read(address)
+        let value = self.bus.try_read(address).unwrap_or(0);
+        self.bus.take_wait_states();
+        value
     }
 
     fn inject(&mut self, address: u16, data: u8) {
-        self.write(address, data);
+        let _ = self.bus.try_write(address, data);
+        self.bus.take_wait_states();
     }
 
     fn get_cursor(&self) -> u16 {
@@ -1041,6 +1150,85 @@ impl<T: AddressBusIO<u16, u8>> Debug<u16, u8> for MOS6502<T> {
         self.requested_code_breakpoint = false;
         requested
     }
+
+    fn resolve(&self, name: &str) -> Option<u16> {
+        match self.symbols.resolve(name) {
+            Ok(address) if address <= 0xffff => Some(address as u16),
+            _ => None,
+        }
+    }
+
+    // BRK returns (with RTI) past its signature byte
+    fn instruction_kind(&mut self) -> InstructionKind<u16> {
+        let pc = self.pc;
+        match self.inspect(pc) {
+            0x20 => InstructionKind::Call(pc.wrapping_add(3)),
+            0x00 => InstructionKind::Call(pc.wrapping_add(2)),
+            0x40 | 0x60 => InstructionKind::Return,
+            _ => InstructionKind::Other,
+        }
+    }
+
+    fn registers(&self) -> Vec<RegisterInfo> {
+        vec![
+            RegisterInfo::new("A", 8),
+            RegisterInfo::new("X", 8),
+            RegisterInfo::new("Y", 8),
+            RegisterInfo::new("SP", 8),
+            RegisterInfo::new("P", 8),
+            RegisterInfo::new("PC", 16),
+        ]
+    }
+
+    fn stack_pointer(&self) -> Option<u64> {
+        Some(u64::from(self.sp))
+    }
+
+    // STATUS is accepted as the long name of P
+    fn register_index(&self, name: &str) -> Option<usize> {
+        if name.eq_ignore_ascii_case("STATUS") {
+            return Some(4);
+        }
+        self.registers()
+            .iter()
+            .position(|register| register.name.eq_ignore_ascii_case(name))
+    }
+
+    fn get_register(&self, index: usize) -> Option<u64> {
+        match index {
+            0 => Some(u64::from(self.a)),
+            1 => Some(u64::from(self.x)),
+            2 => Some(u64::from(self.y)),
+            3 => Some(u64::from(self.sp)),
+            4 => Some(u64::from(self.status)),
+            5 => Some(u64::from(self.pc)),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, index: usize, value: u64) -> bool {
+        match index {
+            0 => self.a = value as u8,
+            1 => self.x = value as u8,
+            2 => self.y = value as u8,
+            3 => self.sp = value as u8,
+            4 => self.status = value as u8 | ALWAYS_SET,
+            5 => self.pc = value as u16,
+            _ => return false,
+        }
+        true
+    }
+
+    fn symbol(&self, address: u16) -> Option<String> {
+        // far away symbols are probably unrelated
+        self.symbols
+            .nearest(u32::from(address))
+            .filter(|(_, offset)| *offset < 0x100)
+            .map(|(name, offset)| match offset {
+                0 => name.to_string(),
+                _ => format!("{}+{}", name, offset),
+            })
+    }
 }
 
 impl<T: AddressBusIO<u16, u8>> Interrupt<u16> for MOS6502<T> {
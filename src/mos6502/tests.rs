@@ -1,7 +1,8 @@
+use memcontroller::MemoryController;
 use mos6502::{CARRY, MOS6502, SIGN, ZERO};
 use ram::Ram;
-use AddressBusIO;
-use Clock;
+use {AddressBusIO, BusError};
+use {Clock, Debug};
 
 #[test]
 fn test_adc_immediate() {
@@ -216,3 +217,79 @@ fn test_ror_accumulator_carry() {
     assert_eq!(cpu.get_flag(ZERO), false);
     assert_eq!(cpu.get_flag(SIGN), true);
 }
+
+#[test]
+fn test_lda_absolute_wait_states() {
+    let mut ram = Ram::new(1024);
+    let mut slow_ram = Ram::new(1024);
+    ram.fill(vec![0xad, 0x00, 0x80], 0);
+    slow_ram.fill(vec![0x17], 0);
+    let mut memory_controller = MemoryController::new();
+    memory_controller.map(0x0000, 0x03ff, &mut ram);
+    memory_controller.map(0x8000, 0x83ff, &mut slow_ram);
+    memory_controller.set_wait_states(0x8000, 0x83ff, 3);
+    let mut cpu = MOS6502::new(memory_controller);
+    cpu.step();
+    assert_eq!(cpu.a, 0x17);
+    assert_eq!(cpu.ticks, 4 + 3);
+}
+
+#[test]
+fn test_lda_absolute_out_of_range_trap() {
+    let mut ram = Ram::new(1024);
+    ram.fill(vec![0xad, 0x00, 0x80, 0xe8], 0);
+    let mut cpu = MOS6502::new(ram);
+    cpu.step();
+    match cpu.trap {
+        Some(BusError::OutOfRange(address)) => assert_eq!(address, 0x8000),
+        _ => panic!("expected an out of range trap"),
+    }
+    // halted until the trap is cleared
+    cpu.step();
+    assert_eq!(cpu.x, 0);
+    cpu.trap = None;
+    cpu.step();
+    assert_eq!(cpu.x, 1);
+}
+
+#[test]
+fn test_indirect_jump_wraps_around() {
+    let mut ram = Ram::new(65536);
+    // JMP ($FFFF)
+    ram.fill(vec![0x6c, 0xff, 0xff], 0);
+    ram.fill(vec![0x34], 0xffff);
+    let mut cpu = MOS6502::new(ram);
+    cpu.step();
+    // the high byte comes from $0000
+    assert_eq!(cpu.pc, 0x6c34);
+}
+
+#[test]
+fn test_inspect_does_not_trap() {
+    let mut ram = Ram::new(1024);
+    ram.fill(vec![0xe8], 0);
+    let mut cpu = MOS6502::new(ram);
+    assert_eq!(cpu.inspect(0x8000), 0);
+    cpu.inject(0x8000, 0x17);
+    assert!(cpu.trap.is_none());
+    cpu.step();
+    assert_eq!(cpu.x, 1);
+}
+
+#[test]
+fn test_debug_line_symbols() {
+    let mut ram = Ram::new(1024);
+    // JSR $0100, LDA $10,X
+    ram.fill(vec![0x20, 0x00, 0x01], 0);
+    ram.fill(vec![0xb5, 0x10], 0x100);
+    let mut cpu = MOS6502::new(ram);
+    cpu.debug = true;
+    cpu.symbols.insert("printstring", 0x100);
+    cpu.symbols.insert("buffer", 0x10);
+    cpu.step();
+    assert!(cpu.debug_line.starts_with("jsr printstring "));
+    cpu.step();
+    assert!(cpu.debug_line.starts_with("lda buffer,X "));
+    assert_eq!(cpu.resolve("printstring+1"), Some(0x101));
+    assert_eq!(cpu.symbol(0x101), Some("printstring+1".to_string()));
+}
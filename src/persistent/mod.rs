@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// the cells of a non volatile device, optionally backed by a file that is
+// written back when dirty (by save() and on drop)
+pub(crate) struct PersistentImage {
+    pub cells: Vec<u8>,
+    filename: Option<PathBuf>,
+    dirty: bool,
+}
+
+impl PersistentImage {
+    pub fn new(size: usize, value: u8) -> PersistentImage {
+        PersistentImage {
+            cells: vec![value; size],
+            filename: None,
+            dirty: false,
+        }
+    }
+
+    // loads the content from the file, a missing file keeps the current content
+    pub fn persist(&mut self, path: &Path) -> io::Result<()> {
+        match fs::read(path) {
+            Ok(data) => {
+                let length = data.len().min(self.cells.len());
+                self.cells[..length].copy_from_slice(&data[..length]);
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        self.filename = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // without a file the content is only marked clean
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.dirty {
+            if let Some(filename) = self.filename.as_ref() {
+                fs::write(filename, &self.cells)?;
+            }
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, index: usize, value: u8) {
+        self.cells[index] = value;
+        self.dirty = true;
+    }
+
+    pub fn fill(&mut self, start: usize, length: usize, value: u8) {
+        for cell in &mut self.cells[start..start + length] {
+            *cell = value;
+        }
+        self.dirty = true;
+    }
+}
+
+impl Drop for PersistentImage {
+    fn drop(&mut self) {
+        let _ = self.save();
+    }
+}
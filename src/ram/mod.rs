@@ -1,31 +1,202 @@
+use rand;
+use rand::distributions::{Distribution, Standard};
 use std::cmp;
-use {Address, AddressBusIO, As, Data};
+use std::io;
+use std::path::Path;
+
+use persistent::PersistentImage;
+use {Address, AddressBusIO, As, BusError, Clock, Data};
 
 pub struct Ram<T: Data> {
     cells: Vec<T>,
+    // shadow map of the cells written since power on, when tracking is enabled
+    written: Option<Vec<bool>>,
+    uninitialized_reads: Vec<usize>,
 }
 
 impl<T: Data> Ram<T> {
     pub fn new(size: usize) -> Ram<T> {
         Ram {
             cells: vec![T::zero(); size],
+            written: None,
+            uninitialized_reads: Vec::new(),
         }
     }
 
+    // loaded data counts as initialized
     pub fn fill(&mut self, data: Vec<T>, offset: usize) {
         let length = offset + cmp::min(data.len(), self.cells.len());
         for (index, i) in (offset..length).enumerate() {
             self.cells[i] = data[index];
+            self.mark_written(i);
+        }
+    }
+
+    // power on garbage, unlike fill() the cells are still considered uninitialized
+    pub fn fill_pattern(&mut self, value: T) {
+        for cell in &mut self.cells {
+            *cell = value;
+        }
+    }
+
+    pub fn randomize(&mut self)
+    where
+        Standard: Distribution<T>,
+    {
+        for cell in &mut self.cells {
+            *cell = rand::random::<T>();
+        }
+    }
+
+    // reads of cells never written are collected (once per cell) for take_uninitialized_reads()
+    pub fn track_uninitialized(&mut self, enable: bool) {
+        self.written = if enable {
+            Some(vec![false; self.cells.len()])
+        } else {
+            None
+        };
+    }
+
+    pub fn take_uninitialized_reads(&mut self) -> Vec<usize> {
+        self.uninitialized_reads.split_off(0)
+    }
+
+    fn mark_written(&mut self, index: usize) {
+        if let Some(written) = self.written.as_mut() {
+            written[index] = true;
+        }
+    }
+
+    fn check_initialized(&mut self, index: usize) {
+        if let Some(written) = self.written.as_mut() {
+            if !written[index] {
+                written[index] = true;
+                self.uninitialized_reads.push(index);
+            }
         }
     }
 }
 
 impl<T: Address + As<usize>, U: Data> AddressBusIO<T, U> for Ram<U> {
     fn read(&mut self, address: T) -> U {
-        self.cells[address.as_()]
+        let index = address.as_();
+        let value = self.cells[index];
+        self.check_initialized(index);
+        value
     }
 
     fn write(&mut self, address: T, value: U) {
-        self.cells[address.as_()] = value;
+        let index = address.as_();
+        self.cells[index] = value;
+        self.mark_written(index);
+    }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let index = address.as_();
+        match self.cells.get(index) {
+            Some(value) => {
+                let value = *value;
+                self.check_initialized(index);
+                Ok(value)
+            }
+            None => Err(BusError::out_of_range(address)),
+        }
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        let index = address.as_();
+        match self.cells.get_mut(index) {
+            Some(cell) => *cell = value,
+            None => return Err(BusError::out_of_range(address)),
+        }
+        self.mark_written(index);
+        Ok(())
+    }
+
+    // not counted as a read of uninitialized memory
+    fn peek(&self, address: T) -> Option<U> {
+        self.cells.get(address.as_()).cloned()
     }
 }
+
+// ram kept alive by a battery: the content is loaded from a file and written back
+// by sync(), every flush_ticks steps and on drop
+pub struct BatteryRam {
+    image: PersistentImage,
+    // 0 disables the periodic flush
+    pub flush_ticks: u64,
+    ticks: u64,
+    error: Option<io::Error>,
+}
+
+impl BatteryRam {
+    // a missing file powers on with zeroed memory, it is created by the first sync
+    pub fn open<P: AsRef<Path>>(filename: P, size: usize) -> io::Result<BatteryRam> {
+        let mut image = PersistentImage::new(size, 0);
+        image.persist(filename.as_ref())?;
+        Ok(BatteryRam {
+            image,
+            flush_ticks: 0,
+            ticks: 0,
+            error: None,
+        })
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.image.is_dirty()
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.image.save()
+    }
+
+    // the reason of the last failed periodic flush
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl Clock for BatteryRam {
+    fn step(&mut self) {
+        if self.flush_ticks == 0 {
+            return;
+        }
+        self.ticks += 1;
+        if self.ticks >= self.flush_ticks {
+            self.ticks = 0;
+            self.error = self.sync().err();
+        }
+    }
+}
+
+impl<T: Address + As<usize>> AddressBusIO<T, u8> for BatteryRam {
+    fn read(&mut self, address: T) -> u8 {
+        self.image.cells[address.as_()]
+    }
+
+    fn write(&mut self, address: T, value: u8) {
+        self.image.set(address.as_(), value);
+    }
+
+    fn try_read(&mut self, address: T) -> Result<u8, BusError> {
+        match self.image.cells.get(address.as_()) {
+            Some(value) => Ok(*value),
+            None => Err(BusError::out_of_range(address)),
+        }
+    }
+
+    fn try_write(&mut self, address: T, value: u8) -> Result<(), BusError> {
+        if address.as_() >= self.image.cells.len() {
+            return Err(BusError::out_of_range(address));
+        }
+        self.image.set(address.as_(), value);
+        Ok(())
+    }
+
+    fn peek(&self, address: T) -> Option<u8> {
+        self.image.cells.get(address.as_()).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,64 @@
+use std::fs;
+
+use ram::{BatteryRam, Ram};
+use utils::temp_path;
+use {AddressBusIO, BusError, Clock};
+
+#[test]
+fn battery_ram_survives_drop() {
+    let path = temp_path("battery_ram_drop");
+    {
+        let mut ram = BatteryRam::open(&path, 256).unwrap();
+        assert_eq!(ram.read(0x10u16), 0);
+        ram.write(0x10u16, 0x17);
+    }
+    let mut ram = BatteryRam::open(&path, 256).unwrap();
+    assert_eq!(ram.read(0x10u16), 0x17);
+    assert!(!ram.is_dirty());
+    match ram.try_write(0x100u16, 0) {
+        Err(BusError::OutOfRange(0x100)) => (),
+        _ => panic!("expected an out of range write"),
+    }
+    drop(ram);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn battery_ram_periodic_flush() {
+    let path = temp_path("battery_ram_flush");
+    let mut ram = BatteryRam::open(&path, 16).unwrap();
+    ram.flush_ticks = 4;
+    ram.write(0x01u16, 0x22);
+    for _ in 0..3 {
+        ram.step();
+    }
+    assert!(ram.is_dirty());
+    assert!(!path.exists());
+    ram.step();
+    assert!(!ram.is_dirty());
+    assert_eq!(fs::read(&path).unwrap()[1], 0x22);
+
+    ram.write(0x02u16, 0x33);
+    ram.sync().unwrap();
+    assert_eq!(fs::read(&path).unwrap()[2], 0x33);
+    drop(ram);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn uninitialized_reads_are_collected() {
+    let mut ram: Ram<u8> = Ram::new(16);
+    ram.randomize();
+    ram.fill_pattern(0xa5);
+    ram.track_uninitialized(true);
+    ram.fill(vec![1, 2], 0);
+    ram.write(0x08u16, 0x10);
+    assert_eq!(ram.read(0x00u16), 1);
+    assert_eq!(ram.read(0x08u16), 0x10);
+    assert_eq!(ram.read(0x04u16), 0xa5);
+    // every cell is reported once
+    assert_eq!(ram.try_read(0x04u16).unwrap(), 0xa5);
+    assert_eq!(ram.try_read(0x05u16).unwrap(), 0xa5);
+    assert_eq!(ram.take_uninitialized_reads(), vec![0x04, 0x05]);
+    assert!(ram.take_uninitialized_reads().is_empty());
+}
@@ -1,4 +1,4 @@
-use {Address, AddressBusIO, As, Data};
+use {Address, AddressBusIO, As, BusError, Data};
 
 pub struct Rom<T: Data> {
     cells: Vec<T>,
@@ -14,4 +14,15 @@ impl<T: Address + As<usize>, U: Data> AddressBusIO<T, U> for Rom<U> {
     fn read(&mut self, address: T) -> U {
         self.cells[address.as_()]
     }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        match self.cells.get(address.as_()) {
+            Some(value) => Ok(*value),
+            None => Err(BusError::out_of_range(address)),
+        }
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        self.cells.get(address.as_()).cloned()
+    }
 }
@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use Clock;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Slot {
+    Device(usize),
+    Event(usize),
+}
+
+struct ScheduledDevice<'a> {
+    device: Rc<RefCell<dyn Clock + 'a>>,
+    divider: u64,
+    // master cycle of the next step
+    next: u64,
+}
+
+struct ScheduledEvent {
+    id: usize,
+    cycle: u64,
+    // 0 for one shot events
+    period: u64,
+}
+
+// advances clocked devices in timestamp order against a master clock
+pub struct Scheduler<'a> {
+    pub hz: u64,
+    devices: Vec<ScheduledDevice<'a>>,
+    events: Vec<ScheduledEvent>,
+    cycle: u64,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(hz: u64) -> Scheduler<'a> {
+        Scheduler {
+            hz,
+            devices: Vec::new(),
+            events: Vec::new(),
+            cycle: 0,
+        }
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    // each cycle of the device lasts divider master cycles
+    pub fn add_device(&mut self, device: Rc<RefCell<dyn Clock + 'a>>, divider: u64) -> usize {
+        if divider == 0 {
+            panic!("invalid divider 0");
+        }
+        self.devices.push(ScheduledDevice {
+            device,
+            divider,
+            next: self.cycle,
+        });
+        self.devices.len() - 1
+    }
+
+    pub fn add_device_hz(&mut self, device: Rc<RefCell<dyn Clock + 'a>>, hz: u64) -> usize {
+        if hz == 0 || hz > self.hz {
+            panic!(
+                "device at {}hz cannot be driven by a {}hz clock",
+                hz, self.hz
+            );
+        }
+        let divider = self.hz / hz;
+        self.add_device(device, divider)
+    }
+
+    pub fn schedule(&mut self, cycle: u64, id: usize) {
+        self.events.push(ScheduledEvent {
+            id,
+            cycle,
+            period: 0,
+        });
+    }
+
+    pub fn schedule_every(&mut self, first: u64, period: u64, id: usize) {
+        if period == 0 {
+            panic!("invalid period 0");
+        }
+        self.events.push(ScheduledEvent {
+            id,
+            cycle: first,
+            period,
+        });
+    }
+
+    // removes every pending occurrence of the event
+    pub fn cancel(&mut self, id: usize) -> bool {
+        let before = self.events.len();
+        self.events.retain(|event| event.id != id);
+        self.events.len() != before
+    }
+
+    fn next_device(&self) -> Option<usize> {
+        let mut next: Option<usize> = None;
+        for (index, device) in self.devices.iter().enumerate() {
+            match next {
+                Some(current) if self.devices[current].next <= device.next => (),
+                _ => next = Some(index),
+            }
+        }
+        next
+    }
+
+    fn next_event(&self) -> Option<usize> {
+        let mut next: Option<usize> = None;
+        for (index, event) in self.events.iter().enumerate() {
+            match next {
+                Some(current) if self.events[current].cycle <= event.cycle => (),
+                _ => next = Some(index),
+            }
+        }
+        next
+    }
+
+    // what the next call to step will do, events win ties against devices
+    pub fn peek(&self) -> Option<Slot> {
+        match (self.next_device(), self.next_event()) {
+            (Some(device), Some(event)) => {
+                if self.events[event].cycle <= self.devices[device].next {
+                    Some(Slot::Event(self.events[event].id))
+                } else {
+                    Some(Slot::Device(device))
+                }
+            }
+            (Some(device), None) => Some(Slot::Device(device)),
+            (None, Some(event)) => Some(Slot::Event(self.events[event].id)),
+            (None, None) => None,
+        }
+    }
+
+    fn next_cycle(&self) -> Option<u64> {
+        match self.peek() {
+            Some(Slot::Device(device)) => Some(self.devices[device].next),
+            Some(Slot::Event(_)) => self.next_event().map(|event| self.events[event].cycle),
+            None => None,
+        }
+    }
+
+    // steps a single device or fires a single event
+    pub fn step(&mut self) -> Option<Slot> {
+        let slot = self.peek();
+        match slot {
+            Some(Slot::Device(index)) => {
+                let device = &mut self.devices[index];
+                self.cycle = device.next;
+                // a halted device still has to let time flow
+                let cycles = device.device.borrow_mut().tick().max(1);
+                device.next += cycles * device.divider;
+            }
+            Some(Slot::Event(_)) => {
+                let index = self.next_event().unwrap();
+                self.cycle = self.events[index].cycle;
+                if self.events[index].period > 0 {
+                    self.events[index].cycle += self.events[index].period;
+                } else {
+                    self.events.remove(index);
+                }
+            }
+            None => (),
+        }
+        slot
+    }
+
+    // returns the events fired while running
+    pub fn run_for(&mut self, cycles: u64) -> Vec<usize> {
+        let end = self.cycle + cycles;
+        let mut fired = Vec::new();
+        while let Some(cycle) = self.next_cycle() {
+            if cycle >= end {
+                break;
+            }
+            if let Some(Slot::Event(id)) = self.step() {
+                fired.push(id);
+            }
+        }
+        self.cycle = end;
+        fired
+    }
+
+    // None when no event is scheduled
+    pub fn run_until_event(&mut self) -> Option<usize> {
+        if self.events.is_empty() {
+            return None;
+        }
+        loop {
+            if let Some(Slot::Event(id)) = self.step() {
+                return Some(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
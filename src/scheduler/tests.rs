@@ -0,0 +1,64 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mos6502::MOS6502;
+use ram::Ram;
+use scheduler::{Scheduler, Slot};
+use Clock;
+
+struct Counter {
+    steps: u64,
+}
+
+impl Clock for Counter {
+    fn step(&mut self) {
+        self.steps += 1;
+    }
+}
+
+#[test]
+fn devices_at_different_rates() {
+    let fast = Rc::new(RefCell::new(Counter { steps: 0 }));
+    let slow = Rc::new(RefCell::new(Counter { steps: 0 }));
+    let mut scheduler = Scheduler::new(4_000_000);
+    scheduler.add_device_hz(fast.clone(), 4_000_000);
+    scheduler.add_device_hz(slow.clone(), 1_000_000);
+    scheduler.run_for(100);
+    assert_eq!(fast.borrow().steps, 100);
+    assert_eq!(slow.borrow().steps, 25);
+    assert_eq!(scheduler.cycle(), 100);
+}
+
+#[test]
+fn cpu_instructions_take_their_cycles() {
+    let mut ram = Ram::new(1024);
+    // lda #$01, lda #$02, lda #$03
+    ram.fill(vec![0xa9, 0x01, 0xa9, 0x02, 0xa9, 0x03], 0);
+    let cpu = Rc::new(RefCell::new(MOS6502::new(ram)));
+    let mut scheduler = Scheduler::new(1_000_000);
+    scheduler.add_device(cpu.clone(), 1);
+    scheduler.run_for(4);
+    assert_eq!(cpu.borrow().a, 0x02);
+}
+
+#[test]
+fn events_fire_in_order() {
+    let counter = Rc::new(RefCell::new(Counter { steps: 0 }));
+    let mut scheduler = Scheduler::new(1000);
+    scheduler.add_device(counter.clone(), 1);
+    scheduler.schedule_every(10, 10, 0);
+    scheduler.schedule(15, 1);
+
+    assert_eq!(scheduler.run_until_event(), Some(0));
+    assert_eq!(scheduler.cycle(), 10);
+    assert_eq!(counter.borrow().steps, 10);
+    assert_eq!(scheduler.peek(), Some(Slot::Device(0)));
+
+    assert_eq!(scheduler.run_until_event(), Some(1));
+    assert_eq!(scheduler.run_until_event(), Some(0));
+    assert_eq!(scheduler.cycle(), 20);
+
+    assert_eq!(scheduler.run_for(25), vec![0, 0]);
+    assert!(scheduler.cancel(0));
+    assert_eq!(scheduler.run_until_event(), None);
+}
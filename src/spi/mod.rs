@@ -0,0 +1,95 @@
+use {Address, AddressBusIO, As};
+
+pub mod sdcard;
+
+// a slave on the bus, every transfer shifts a byte out while shifting one in
+pub trait SpiDevice {
+    fn transfer(&mut self, value: u8) -> u8;
+    // chip select edges, slaves reset their framing on deselect
+    fn select(&mut self, _selected: bool) {}
+}
+
+// registers: $00 data (writing starts a transfer, reading returns the last byte received),
+// $01 control (bit 7 reads as ready, transfers complete immediately),
+// $02 chip select (bit N selects device N)
+pub struct SpiMaster {
+    devices: Vec<Box<dyn SpiDevice>>,
+    data: u8,
+    control: u8,
+    chip_select: u8,
+}
+
+const READY: u8 = 0x80;
+
+impl SpiMaster {
+    pub fn new() -> SpiMaster {
+        SpiMaster {
+            devices: Vec::new(),
+            data: 0xff,
+            control: 0,
+            chip_select: 0,
+        }
+    }
+
+    // returns the chip select line of the device
+    pub fn attach(&mut self, device: Box<dyn SpiDevice>) -> usize {
+        if self.devices.len() == 8 {
+            panic!("no more chip select lines available");
+        }
+        self.devices.push(device);
+        self.devices.len() - 1
+    }
+
+    pub fn select(&mut self, chip_select: u8) {
+        for (line, device) in self.devices.iter_mut().enumerate() {
+            let selected = chip_select & (1 << line) != 0;
+            if selected != (self.chip_select & (1 << line) != 0) {
+                device.select(selected);
+            }
+        }
+        self.chip_select = chip_select;
+    }
+
+    pub fn transfer(&mut self, value: u8) -> u8 {
+        // MISO is pulled up when nobody drives it
+        let mut received = 0xff;
+        for (line, device) in self.devices.iter_mut().enumerate() {
+            if self.chip_select & (1 << line) != 0 {
+                received &= device.transfer(value);
+            }
+        }
+        self.data = received;
+        received
+    }
+}
+
+impl Default for SpiMaster {
+    fn default() -> SpiMaster {
+        SpiMaster::new()
+    }
+}
+
+impl<T: Address + As<usize>> AddressBusIO<T, u8> for SpiMaster {
+    fn write(&mut self, address: T, value: u8) {
+        match address.as_() {
+            0x00 => {
+                self.transfer(value);
+            }
+            0x01 => self.control = value & !READY,
+            0x02 => self.select(value),
+            _ => (),
+        }
+    }
+
+    fn read(&mut self, address: T) -> u8 {
+        match address.as_() {
+            0x00 => self.data,
+            0x01 => self.control | READY,
+            0x02 => self.chip_select,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+
+use spi::SpiDevice;
+use storage::BlockDevice;
+
+const BLOCK_SIZE: usize = 512;
+
+// R1 flags
+const R1_READY: u8 = 0x00;
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+const R1_ADDRESS_ERROR: u8 = 0x20;
+
+const DATA_TOKEN: u8 = 0xfe;
+const DATA_ACCEPTED: u8 = 0x05;
+const DATA_WRITE_ERROR: u8 = 0x0d;
+
+enum State {
+    Command,
+    WaitToken(u32),
+    Receive(u32, Vec<u8>),
+}
+
+// SDHC card in SPI mode, block addressed, CRCs are neither checked nor generated
+pub struct SdCard {
+    block_device: BlockDevice,
+    selected: bool,
+    idle: bool,
+    app_command: bool,
+    command: Vec<u8>,
+    response: VecDeque<u8>,
+    state: State,
+    pub cid: [u8; 16],
+}
+
+impl SdCard {
+    pub fn new(block_device: BlockDevice) -> SdCard {
+        if block_device.block_size != BLOCK_SIZE {
+            panic!("sd cards need {} bytes blocks", BLOCK_SIZE);
+        }
+        SdCard {
+            block_device,
+            selected: false,
+            idle: true,
+            app_command: false,
+            command: Vec::with_capacity(6),
+            response: VecDeque::new(),
+            state: State::Command,
+            // manufacturer, "IM", "IMPST", revision 1.0, serial, 2020/01
+            cid: [
+                0x00, b'I', b'M', b'I', b'M', b'P', b'S', b'T', 0x10, 0x00, 0x00, 0x00, 0x01, 0x01,
+                0x41, 0x01,
+            ],
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.block_device.flush()
+    }
+
+    // version 2.0 layout, C_SIZE counts units of 512KiB
+    pub fn csd(&self) -> [u8; 16] {
+        let units = self.block_device.block_count() / 1024;
+        let c_size = if units > 0 { units - 1 } else { 0 };
+        [
+            0x40,
+            0x0e,
+            0x00,
+            0x32,
+            0x5b,
+            0x59,
+            0x00,
+            ((c_size >> 16) & 0x3f) as u8,
+            ((c_size >> 8) & 0xff) as u8,
+            (c_size & 0xff) as u8,
+            0x7f,
+            0x80,
+            0x0a,
+            0x40,
+            0x00,
+            0x01,
+        ]
+    }
+
+    fn r1(&self, flags: u8) -> u8 {
+        if self.idle {
+            flags | R1_IDLE
+        } else {
+            flags
+        }
+    }
+
+    fn respond(&mut self, bytes: &[u8]) {
+        // one byte of command response time (NCR)
+        self.response.push_back(0xff);
+        self.response.extend(bytes.iter());
+    }
+
+    fn respond_data(&mut self, r1: u8, data: &[u8]) {
+        self.respond(&[r1, 0xff, DATA_TOKEN]);
+        self.response.extend(data.iter());
+        // dummy crc
+        self.response.extend([0xff, 0xff].iter());
+    }
+
+    fn execute(&mut self) {
+        let index = self.command[0] & 0x3f;
+        let argument = (u32::from(self.command[1]) << 24)
+            | (u32::from(self.command[2]) << 16)
+            | (u32::from(self.command[3]) << 8)
+            | u32::from(self.command[4]);
+        let app_command = self.app_command;
+        self.app_command = false;
+
+        match (app_command, index) {
+            // GO_IDLE_STATE
+            (_, 0) => {
+                self.idle = true;
+                self.respond(&[R1_IDLE]);
+            }
+            // SEND_IF_COND, echo voltage and check pattern
+            (_, 8) => {
+                let r1 = self.r1(R1_READY);
+                self.respond(&[r1, 0x00, 0x00, (argument >> 8) as u8 & 0x0f, argument as u8]);
+            }
+            // APP_CMD
+            (_, 55) => {
+                self.app_command = true;
+                let r1 = self.r1(R1_READY);
+                self.respond(&[r1]);
+            }
+            // SD_SEND_OP_COND, initialization completes immediately
+            (true, 41) => {
+                self.idle = false;
+                self.respond(&[R1_READY]);
+            }
+            // READ_OCR, powered up and high capacity
+            (_, 58) => {
+                let r1 = self.r1(R1_READY);
+                self.respond(&[r1, 0xc0, 0xff, 0x80, 0x00]);
+            }
+            _ if self.idle => {
+                self.respond(&[R1_IDLE | R1_ILLEGAL_COMMAND]);
+            }
+            // SEND_CSD
+            (_, 9) => {
+                let csd = self.csd();
+                self.respond_data(R1_READY, &csd);
+            }
+            // SEND_CID
+            (_, 10) => {
+                let cid = self.cid;
+                self.respond_data(R1_READY, &cid);
+            }
+            // SET_BLOCKLEN, fixed to 512 bytes on high capacity cards
+            (_, 16) => {
+                let r1 = if argument as usize == BLOCK_SIZE {
+                    R1_READY
+                } else {
+                    R1_ILLEGAL_COMMAND
+                };
+                self.respond(&[r1]);
+            }
+            // READ_SINGLE_BLOCK
+            (_, 17) => {
+                let mut buffer = vec![0; BLOCK_SIZE];
+                match self
+                    .block_device
+                    .read_block(u64::from(argument), &mut buffer)
+                {
+                    Ok(()) => self.respond_data(R1_READY, &buffer),
+                    Err(_) => self.respond(&[R1_ADDRESS_ERROR]),
+                }
+            }
+            // WRITE_BLOCK
+            (_, 24) => {
+                // growable images are extended by the write itself
+                let in_range = u64::from(argument) < self.block_device.block_count()
+                    || self.block_device.is_growable();
+                if in_range && !self.block_device.is_read_only() {
+                    self.respond(&[R1_READY]);
+                    self.state = State::WaitToken(argument);
+                } else {
+                    self.respond(&[R1_ADDRESS_ERROR]);
+                }
+            }
+            _ => {
+                self.respond(&[R1_ILLEGAL_COMMAND]);
+            }
+        }
+    }
+
+    fn receive(&mut self, block: u32, mut buffer: Vec<u8>, value: u8) {
+        buffer.push(value);
+        // data followed by two crc bytes
+        if buffer.len() < BLOCK_SIZE + 2 {
+            self.state = State::Receive(block, buffer);
+            return;
+        }
+        buffer.truncate(BLOCK_SIZE);
+        let token = match self.block_device.write_block(u64::from(block), &buffer) {
+            Ok(()) => DATA_ACCEPTED,
+            Err(_) => DATA_WRITE_ERROR,
+        };
+        // data response followed by a busy byte
+        self.response.push_back(token);
+        self.response.push_back(0x00);
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn transfer(&mut self, value: u8) -> u8 {
+        if !self.selected {
+            return 0xff;
+        }
+        let output = self.response.pop_front().unwrap_or(0xff);
+
+        match mem::replace(&mut self.state, State::Command) {
+            State::WaitToken(block) => {
+                if value == DATA_TOKEN {
+                    self.state = State::Receive(block, Vec::with_capacity(BLOCK_SIZE + 2));
+                } else {
+                    self.state = State::WaitToken(block);
+                }
+            }
+            State::Receive(block, buffer) => self.receive(block, buffer, value),
+            State::Command => {
+                // a command starts with 01 in the top bits
+                if !self.command.is_empty() || value & 0xc0 == 0x40 {
+                    self.command.push(value);
+                    if self.command.len() == 6 {
+                        self.response.clear();
+                        self.execute();
+                        self.command.clear();
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    fn select(&mut self, selected: bool) {
+        self.selected = selected;
+        if !selected {
+            self.command.clear();
+            self.response.clear();
+            self.state = State::Command;
+        }
+    }
+}
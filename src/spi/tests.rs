@@ -0,0 +1,156 @@
+use spi::sdcard::SdCard;
+use spi::{SpiDevice, SpiMaster};
+use storage::{BlockDevice, MemoryStorage};
+use AddressBusIO;
+
+struct Echo {
+    selected: bool,
+}
+
+impl SpiDevice for Echo {
+    fn transfer(&mut self, value: u8) -> u8 {
+        value
+    }
+
+    fn select(&mut self, selected: bool) {
+        self.selected = selected;
+    }
+}
+
+#[test]
+fn master_registers() {
+    let mut spi = SpiMaster::new();
+    spi.attach(Box::new(Echo { selected: false }));
+    spi.write(0x00u16, 0x17);
+    assert_eq!(spi.read(0x00u16), 0xff);
+    spi.write(0x02u16, 0x01);
+    spi.write(0x00u16, 0x17);
+    assert_eq!(spi.read(0x00u16), 0x17);
+    assert_eq!(spi.read(0x01u16) & 0x80, 0x80);
+}
+
+fn command(spi: &mut SpiMaster, index: u8, argument: u32) -> u8 {
+    spi.transfer(0x40 | index);
+    spi.transfer((argument >> 24) as u8);
+    spi.transfer((argument >> 16) as u8);
+    spi.transfer((argument >> 8) as u8);
+    spi.transfer(argument as u8);
+    spi.transfer(0x95);
+    // wait for R1
+    for _ in 0..8 {
+        let r1 = spi.transfer(0xff);
+        if r1 != 0xff {
+            return r1;
+        }
+    }
+    panic!("no response to CMD{}", index);
+}
+
+fn wait_token(spi: &mut SpiMaster) {
+    for _ in 0..8 {
+        if spi.transfer(0xff) == 0xfe {
+            return;
+        }
+    }
+    panic!("no data token");
+}
+
+fn sd_card() -> SpiMaster {
+    let mut data = vec![0; 512 * 2048];
+    data[512] = 0x17;
+    let block_device = BlockDevice::new(Box::new(MemoryStorage::from_vec(data, 512)));
+    let mut spi = SpiMaster::new();
+    spi.attach(Box::new(SdCard::new(block_device)));
+    spi.select(0x01);
+    spi
+}
+
+fn initialize(spi: &mut SpiMaster) {
+    assert_eq!(command(spi, 0, 0), 0x01);
+    assert_eq!(command(spi, 8, 0x1aa), 0x01);
+    let r7: Vec<u8> = (0..4).map(|_| spi.transfer(0xff)).collect();
+    assert_eq!(r7, vec![0x00, 0x00, 0x01, 0xaa]);
+    assert_eq!(command(spi, 55, 0), 0x01);
+    assert_eq!(command(spi, 41, 0x4000_0000), 0x00);
+}
+
+#[test]
+fn sd_card_init_and_registers() {
+    let mut spi = sd_card();
+    // reads are rejected before initialization
+    assert_eq!(command(&mut spi, 0, 0), 0x01);
+    assert_eq!(command(&mut spi, 17, 0), 0x05);
+    initialize(&mut spi);
+
+    assert_eq!(command(&mut spi, 9, 0), 0x00);
+    wait_token(&mut spi);
+    let csd: Vec<u8> = (0..16).map(|_| spi.transfer(0xff)).collect();
+    assert_eq!(csd[0], 0x40);
+    // 2048 blocks are 2 units of 512KiB
+    assert_eq!(csd[9], 1);
+
+    assert_eq!(command(&mut spi, 10, 0), 0x00);
+    wait_token(&mut spi);
+    let cid: Vec<u8> = (0..16).map(|_| spi.transfer(0xff)).collect();
+    assert_eq!(&cid[1..3], b"IM");
+}
+
+#[test]
+fn sd_card_read_and_write() {
+    let mut spi = sd_card();
+    initialize(&mut spi);
+
+    assert_eq!(command(&mut spi, 17, 1), 0x00);
+    wait_token(&mut spi);
+    let block: Vec<u8> = (0..512).map(|_| spi.transfer(0xff)).collect();
+    assert_eq!(block[0], 0x17);
+    spi.transfer(0xff);
+    spi.transfer(0xff);
+
+    assert_eq!(command(&mut spi, 24, 2), 0x00);
+    spi.transfer(0xff);
+    spi.transfer(0xfe);
+    for index in 0..512 {
+        spi.transfer(index as u8);
+    }
+    spi.transfer(0xff);
+    spi.transfer(0xff);
+    assert_eq!(spi.transfer(0xff) & 0x1f, 0x05);
+
+    assert_eq!(command(&mut spi, 17, 2), 0x00);
+    wait_token(&mut spi);
+    let block: Vec<u8> = (0..512).map(|_| spi.transfer(0xff)).collect();
+    assert_eq!(block[511], 0xff);
+    assert_eq!(block[3], 3);
+
+    assert_eq!(command(&mut spi, 17, 4096), 0x20);
+}
+
+#[test]
+fn sd_card_grows_fresh_images() {
+    let mut storage = MemoryStorage::new(512, 0);
+    storage.growable = true;
+    let mut spi = SpiMaster::new();
+    spi.attach(Box::new(SdCard::new(BlockDevice::new(Box::new(storage)))));
+    spi.select(0x01);
+    initialize(&mut spi);
+
+    assert_eq!(command(&mut spi, 24, 3), 0x00);
+    spi.transfer(0xff);
+    spi.transfer(0xfe);
+    for _ in 0..514 {
+        spi.transfer(0x17);
+    }
+    assert_eq!(spi.transfer(0xff) & 0x1f, 0x05);
+
+    assert_eq!(command(&mut spi, 17, 3), 0x00);
+    wait_token(&mut spi);
+    assert_eq!(spi.transfer(0xff), 0x17);
+}
+
+#[test]
+fn sd_card_refuses_writes_past_fixed_images() {
+    let mut spi = sd_card();
+    initialize(&mut spi);
+    assert_eq!(command(&mut spi, 24, 2048), 0x20);
+}
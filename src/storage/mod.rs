@@ -1,5 +1,7 @@
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
@@ -8,55 +10,504 @@ use std::path::Path;
 
 use {Address, AddressBusBlockIO, As};
 
-pub struct BlockDevice {
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn out_of_range(block: u64, block_count: u64) -> io::Error {
+    invalid_input(format!(
+        "block {} out of range ({} blocks)",
+        block, block_count
+    ))
+}
+
+fn check_buffer(length: usize, block_size: usize) -> io::Result<()> {
+    if length != block_size {
+        return Err(invalid_input(format!(
+            "buffer of {} bytes for blocks of {} bytes",
+            length, block_size
+        )));
+    }
+    Ok(())
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "read-only image")
+}
+
+// a backend for block devices, buffers are always block_size bytes long
+pub trait BlockStorage {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+    fn read_block(&mut self, block: u64, buffer: &mut [u8]) -> io::Result<()>;
+    fn write_block(&mut self, block: u64, buffer: &[u8]) -> io::Result<()>;
+    // makes pending writes durable
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    fn is_read_only(&self) -> bool {
+        false
+    }
+    // whether writes past the last block extend the storage
+    fn is_growable(&self) -> bool {
+        false
+    }
+}
+
+pub struct FileStorage {
     file: File,
-    pub block_size: usize,
-    max_size: u64,
+    block_size: usize,
+    size: u64,
+    // growable images are extended by writes past their end
+    pub growable: bool,
+    read_only: bool,
 }
 
-impl BlockDevice {
-    pub fn new(mut file: File, block_size: usize) -> BlockDevice {
-        let max_size = file.seek(SeekFrom::End(0)).unwrap();
-        BlockDevice {
+impl FileStorage {
+    // fixed-size image backed by an already opened file
+    pub fn new(mut file: File, block_size: usize) -> io::Result<FileStorage> {
+        if block_size == 0 {
+            return Err(invalid_input("invalid block size 0".to_string()));
+        }
+        let size = file.seek(SeekFrom::End(0))?;
+        Ok(FileStorage {
             file,
             block_size,
-            max_size,
-        }
+            size,
+            growable: false,
+            read_only: false,
+        })
     }
 
-    pub fn from_filename<P: AsRef<Path>>(filename: P, block_size: usize) -> BlockDevice {
-        let mut file = OpenOptions::new()
+    // the image is created when missing and grows on demand
+    pub fn from_filename<P: AsRef<Path>>(
+        filename: P,
+        block_size: usize,
+    ) -> io::Result<FileStorage> {
+        let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(filename)
-            .unwrap();
-        let max_size = file.seek(SeekFrom::End(0)).unwrap();
-        BlockDevice {
-            file: file,
+            .truncate(false)
+            .open(filename)?;
+        let mut storage = FileStorage::new(file, block_size)?;
+        storage.growable = true;
+        Ok(storage)
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(
+        filename: P,
+        block_size: usize,
+    ) -> io::Result<FileStorage> {
+        let file = File::open(filename)?;
+        let mut storage = FileStorage::new(file, block_size)?;
+        storage.read_only = true;
+        Ok(storage)
+    }
+}
+
+impl BlockStorage for FileStorage {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    // only whole blocks are counted, a truncated tail is not addressable
+    fn block_count(&self) -> u64 {
+        self.size / self.block_size as u64
+    }
+
+    fn read_block(&mut self, block: u64, buffer: &mut [u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size)?;
+        if block >= self.block_count() {
+            return Err(out_of_range(block, self.block_count()));
+        }
+        self.file
+            .seek(SeekFrom::Start(block * self.block_size as u64))?;
+        self.file.read_exact(buffer)
+    }
+
+    fn write_block(&mut self, block: u64, buffer: &[u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size)?;
+        if self.read_only {
+            return Err(read_only());
+        }
+        if block >= self.block_count() && !self.growable {
+            return Err(out_of_range(block, self.block_count()));
+        }
+        let offset = block * self.block_size as u64;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(buffer)?;
+        if offset + self.block_size as u64 > self.size {
+            self.size = offset + self.block_size as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.file.sync_all()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn is_growable(&self) -> bool {
+        self.growable && !self.read_only
+    }
+}
+
+pub struct MemoryStorage {
+    data: Vec<u8>,
+    block_size: usize,
+    pub growable: bool,
+}
+
+impl MemoryStorage {
+    pub fn new(block_size: usize, block_count: usize) -> MemoryStorage {
+        MemoryStorage::from_vec(vec![0; block_size * block_count], block_size)
+    }
+
+    pub fn from_vec(data: Vec<u8>, block_size: usize) -> MemoryStorage {
+        if block_size == 0 {
+            panic!("invalid block size 0");
+        }
+        MemoryStorage {
+            data,
             block_size,
-            max_size,
+            growable: false,
         }
     }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
-impl<T: Address + As<usize>> AddressBusBlockIO<T, u8> for BlockDevice {
-    fn read(&mut self, address: T, buffer: &mut [u8]) {
-        let offset = (address.as_() * self.block_size) as u64;
-        if offset + self.block_size as u64 >= self.max_size {
-            return;
+impl BlockStorage for MemoryStorage {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.len() / self.block_size) as u64
+    }
+
+    fn read_block(&mut self, block: u64, buffer: &mut [u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size)?;
+        if block >= self.block_count() {
+            return Err(out_of_range(block, self.block_count()));
+        }
+        let offset = block as usize * self.block_size;
+        buffer.copy_from_slice(&self.data[offset..offset + self.block_size]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buffer: &[u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size)?;
+        if block >= self.block_count() {
+            if !self.growable {
+                return Err(out_of_range(block, self.block_count()));
+            }
+            self.data.resize((block as usize + 1) * self.block_size, 0);
+        }
+        let offset = block as usize * self.block_size;
+        self.data[offset..offset + self.block_size].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn is_growable(&self) -> bool {
+        self.growable
+    }
+}
+
+// copy-on-write: changed blocks stay in memory, the base image is never written
+pub struct OverlayStorage {
+    base: Box<dyn BlockStorage>,
+    blocks: HashMap<u64, Vec<u8>>,
+    block_count: u64,
+}
+
+impl OverlayStorage {
+    pub fn new(base: Box<dyn BlockStorage>) -> OverlayStorage {
+        let block_count = base.block_count();
+        OverlayStorage {
+            base,
+            blocks: HashMap::new(),
+            block_count,
+        }
+    }
+
+    pub fn changed_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // goes back to the pristine base image
+    pub fn discard(&mut self) {
+        self.blocks.clear();
+        self.block_count = self.base.block_count();
+    }
+}
+
+impl BlockStorage for OverlayStorage {
+    fn block_size(&self) -> usize {
+        self.base.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_block(&mut self, block: u64, buffer: &mut [u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size())?;
+        if let Some(data) = self.blocks.get(&block) {
+            buffer.copy_from_slice(data);
+            return Ok(());
+        }
+        if block < self.base.block_count() {
+            return self.base.read_block(block, buffer);
+        }
+        if block < self.block_count {
+            // gap left by a write past the end of the base image
+            for value in buffer.iter_mut() {
+                *value = 0;
+            }
+            return Ok(());
+        }
+        Err(out_of_range(block, self.block_count))
+    }
+
+    fn write_block(&mut self, block: u64, buffer: &[u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size())?;
+        self.blocks.insert(block, buffer.to_vec());
+        if block >= self.block_count {
+            self.block_count = block + 1;
+        }
+        Ok(())
+    }
+
+    fn is_growable(&self) -> bool {
+        true
+    }
+}
+
+struct CachedBlock {
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+// write-back cache, dirty blocks reach the storage on flush or eviction
+pub struct BlockCache {
+    storage: Box<dyn BlockStorage>,
+    blocks: HashMap<u64, CachedBlock>,
+    // least recently used first
+    order: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl BlockCache {
+    pub fn new(storage: Box<dyn BlockStorage>, capacity: usize) -> BlockCache {
+        BlockCache {
+            storage,
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn dirty_blocks(&self) -> usize {
+        self.blocks.values().filter(|block| block.dirty).count()
+    }
+
+    fn touch(&mut self, block: u64) {
+        if let Some(position) = self.order.iter().position(|cached| *cached == block) {
+            self.order.remove(position);
         }
-        self.file.seek(SeekFrom::Start(offset)).unwrap();
-        self.file.read_exact(buffer).unwrap();
+        self.order.push_back(block);
     }
 
-    fn write(&mut self, address: T, buffer: &[u8]) {
-        let offset = (address.as_() * self.block_size) as u64;
-        if offset + self.block_size as u64 >= self.max_size {
-            return;
+    // a dirty block failing to be written back stays cached
+    fn insert(&mut self, block: u64, cached_block: CachedBlock) -> io::Result<()> {
+        if !self.blocks.contains_key(&block) && self.blocks.len() >= self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                if let Some(evicted_block) = self.blocks.remove(&evicted) {
+                    if evicted_block.dirty {
+                        if let Err(err) = self.storage.write_block(evicted, &evicted_block.data) {
+                            self.blocks.insert(evicted, evicted_block);
+                            self.order.push_front(evicted);
+                            return Err(err);
+                        }
+                    }
+                }
+            }
         }
-        self.file.seek(SeekFrom::Start(offset)).unwrap();
-        self.file.write_all(buffer).unwrap();
-        self.file.sync_all().unwrap();
+        self.blocks.insert(block, cached_block);
+        self.touch(block);
+        Ok(())
     }
 }
+
+impl BlockStorage for BlockCache {
+    fn block_size(&self) -> usize {
+        self.storage.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        let mut block_count = self.storage.block_count();
+        for block in self.blocks.keys() {
+            if *block >= block_count {
+                block_count = block + 1;
+            }
+        }
+        block_count
+    }
+
+    fn read_block(&mut self, block: u64, buffer: &mut [u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size())?;
+        if let Some(cached_block) = self.blocks.get(&block) {
+            buffer.copy_from_slice(&cached_block.data);
+        } else if block >= self.storage.block_count() && block < self.block_count() {
+            // gap left by a cached write past the end of the storage
+            for value in buffer.iter_mut() {
+                *value = 0;
+            }
+            return Ok(());
+        } else {
+            self.storage.read_block(block, buffer)?;
+            self.insert(
+                block,
+                CachedBlock {
+                    data: buffer.to_vec(),
+                    dirty: false,
+                },
+            )?;
+            return Ok(());
+        }
+        self.touch(block);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buffer: &[u8]) -> io::Result<()> {
+        check_buffer(buffer.len(), self.block_size())?;
+        if self.storage.is_read_only() {
+            return Err(read_only());
+        }
+        // refused now, a failing write back would not reach the guest
+        if block >= self.block_count() && !self.storage.is_growable() {
+            return Err(out_of_range(block, self.block_count()));
+        }
+        self.insert(
+            block,
+            CachedBlock {
+                data: buffer.to_vec(),
+                dirty: true,
+            },
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self
+            .blocks
+            .iter()
+            .filter(|&(_, cached_block)| cached_block.dirty)
+            .map(|(block, _)| *block)
+            .collect();
+        // ascending order keeps growable images free of holes while writing
+        dirty.sort();
+        for block in dirty {
+            let cached_block = self.blocks.get_mut(&block).unwrap();
+            self.storage.write_block(block, &cached_block.data)?;
+            cached_block.dirty = false;
+        }
+        self.storage.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.storage.is_read_only()
+    }
+
+    fn is_growable(&self) -> bool {
+        self.storage.is_growable()
+    }
+}
+
+// best effort, hosts should flush explicitly to see errors
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+pub struct BlockDevice {
+    storage: Box<dyn BlockStorage>,
+    pub block_size: usize,
+}
+
+impl BlockDevice {
+    pub fn new(storage: Box<dyn BlockStorage>) -> BlockDevice {
+        let block_size = storage.block_size();
+        BlockDevice {
+            storage,
+            block_size,
+        }
+    }
+
+    // growable file image behind a write-back cache
+    pub fn from_filename<P: AsRef<Path>>(
+        filename: P,
+        block_size: usize,
+    ) -> io::Result<BlockDevice> {
+        let storage = FileStorage::from_filename(filename, block_size)?;
+        Ok(BlockDevice::new(Box::new(BlockCache::new(
+            Box::new(storage),
+            64,
+        ))))
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(
+        filename: P,
+        block_size: usize,
+    ) -> io::Result<BlockDevice> {
+        let storage = FileStorage::open_read_only(filename, block_size)?;
+        Ok(BlockDevice::new(Box::new(storage)))
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.storage.is_read_only()
+    }
+
+    pub fn block_count(&self) -> u64 {
+        self.storage.block_count()
+    }
+
+    pub fn is_growable(&self) -> bool {
+        self.storage.is_growable()
+    }
+
+    pub fn read_block(&mut self, block: u64, buffer: &mut [u8]) -> io::Result<()> {
+        self.storage.read_block(block, buffer)
+    }
+
+    pub fn write_block(&mut self, block: u64, buffer: &[u8]) -> io::Result<()> {
+        self.storage.write_block(block, buffer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.storage.flush()
+    }
+}
+
+impl<T: Address + As<usize>> AddressBusBlockIO<T, u8> for BlockDevice {
+    fn read(&mut self, address: T, buffer: &mut [u8]) -> io::Result<()> {
+        self.read_block(address.as_() as u64, buffer)
+    }
+
+    fn write(&mut self, address: T, buffer: &[u8]) -> io::Result<()> {
+        self.write_block(address.as_() as u64, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests;
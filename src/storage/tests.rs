@@ -0,0 +1,175 @@
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+
+use storage::{BlockCache, BlockDevice, BlockStorage, FileStorage, MemoryStorage, OverlayStorage};
+use utils::temp_path;
+
+#[test]
+fn growable_image() {
+    let path = temp_path("storage_growable");
+    let _ = fs::remove_file(&path);
+    let mut storage = FileStorage::from_filename(&path, 4).unwrap();
+    assert_eq!(storage.block_count(), 0);
+    storage.write_block(1, &[1, 2, 3, 4]).unwrap();
+    assert_eq!(storage.block_count(), 2);
+    let mut buffer = [0xff; 4];
+    storage.read_block(0, &mut buffer).unwrap();
+    assert_eq!(buffer, [0, 0, 0, 0]);
+    // the last block is readable too
+    storage.read_block(1, &mut buffer).unwrap();
+    assert_eq!(buffer, [1, 2, 3, 4]);
+    assert!(storage.read_block(2, &mut buffer).is_err());
+    drop(storage);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fixed_size_image() {
+    let path = temp_path("storage_fixed");
+    fs::write(&path, vec![0; 10]).unwrap();
+    let mut storage = FileStorage::from_filename(&path, 4).unwrap();
+    storage.growable = false;
+    assert_eq!(storage.block_count(), 2);
+    assert!(storage.write_block(1, &[1, 2, 3, 4]).is_ok());
+    assert!(storage.write_block(2, &[1, 2, 3, 4]).is_err());
+    assert!(storage.write_block(0, &[1, 2]).is_err());
+    drop(storage);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn read_only_image() {
+    let path = temp_path("storage_read_only");
+    fs::write(&path, vec![7; 8]).unwrap();
+    let mut block_device = BlockDevice::open_read_only(&path, 4).unwrap();
+    assert!(block_device.is_read_only());
+    let mut buffer = [0; 4];
+    block_device.read_block(1, &mut buffer).unwrap();
+    assert_eq!(buffer, [7; 4]);
+    assert!(block_device.write_block(0, &buffer).is_err());
+    assert!(BlockDevice::open_read_only(temp_path("missing_image"), 4).is_err());
+    drop(block_device);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn memory_storage() {
+    let mut storage = MemoryStorage::new(4, 2);
+    storage.write_block(1, &[1, 2, 3, 4]).unwrap();
+    assert!(storage.write_block(2, &[1, 2, 3, 4]).is_err());
+    storage.growable = true;
+    storage.write_block(3, &[5, 6, 7, 8]).unwrap();
+    assert_eq!(storage.block_count(), 4);
+    assert_eq!(&storage.data()[4..8], &[1, 2, 3, 4]);
+}
+
+#[test]
+fn overlay_keeps_the_base_untouched() {
+    let path = temp_path("storage_overlay");
+    fs::write(&path, vec![7; 8]).unwrap();
+    let base = FileStorage::open_read_only(&path, 4).unwrap();
+    let mut overlay = OverlayStorage::new(Box::new(base));
+    overlay.write_block(0, &[1, 2, 3, 4]).unwrap();
+    overlay.write_block(3, &[5, 6, 7, 8]).unwrap();
+    assert_eq!(overlay.block_count(), 4);
+
+    let mut buffer = [0; 4];
+    overlay.read_block(0, &mut buffer).unwrap();
+    assert_eq!(buffer, [1, 2, 3, 4]);
+    overlay.read_block(1, &mut buffer).unwrap();
+    assert_eq!(buffer, [7; 4]);
+    overlay.read_block(2, &mut buffer).unwrap();
+    assert_eq!(buffer, [0; 4]);
+
+    overlay.flush().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), vec![7; 8]);
+    overlay.discard();
+    overlay.read_block(0, &mut buffer).unwrap();
+    assert_eq!(buffer, [7; 4]);
+    drop(overlay);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn write_back_cache() {
+    let path = temp_path("storage_cache");
+    let _ = fs::remove_file(&path);
+    let storage = FileStorage::from_filename(&path, 4).unwrap();
+    let mut cache = BlockCache::new(Box::new(storage), 2);
+    cache.write_block(0, &[1, 1, 1, 1]).unwrap();
+    cache.write_block(1, &[2, 2, 2, 2]).unwrap();
+    assert_eq!(cache.dirty_blocks(), 2);
+    assert_eq!(fs::read(&path).unwrap().len(), 0);
+
+    // block 0 is the least recently used one and gets written back
+    cache.write_block(2, &[3, 3, 3, 3]).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), vec![1, 1, 1, 1]);
+
+    cache.flush().unwrap();
+    assert_eq!(cache.dirty_blocks(), 0);
+    assert_eq!(fs::read(&path).unwrap().len(), 12);
+    let mut buffer = [0; 4];
+    cache.read_block(0, &mut buffer).unwrap();
+    assert_eq!(buffer, [1; 4]);
+    drop(cache);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn cache_refuses_writes_past_fixed_storage() {
+    let mut cache = BlockCache::new(Box::new(MemoryStorage::new(4, 2)), 4);
+    assert!(cache.write_block(2, &[1, 2, 3, 4]).is_err());
+    assert_eq!(cache.block_count(), 2);
+    assert_eq!(cache.dirty_blocks(), 0);
+}
+
+// fails every write while the flag is set
+struct FailingStorage {
+    storage: MemoryStorage,
+    failing: Rc<Cell<bool>>,
+}
+
+impl BlockStorage for FailingStorage {
+    fn block_size(&self) -> usize {
+        self.storage.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.storage.block_count()
+    }
+
+    fn read_block(&mut self, block: u64, buffer: &mut [u8]) -> io::Result<()> {
+        self.storage.read_block(block, buffer)
+    }
+
+    fn write_block(&mut self, block: u64, buffer: &[u8]) -> io::Result<()> {
+        if self.failing.get() {
+            return Err(io::Error::other("write failed"));
+        }
+        self.storage.write_block(block, buffer)
+    }
+}
+
+#[test]
+fn cache_keeps_blocks_failing_write_back() {
+    let failing = Rc::new(Cell::new(true));
+    let storage = FailingStorage {
+        storage: MemoryStorage::new(4, 4),
+        failing: failing.clone(),
+    };
+    let mut cache = BlockCache::new(Box::new(storage), 1);
+    cache.write_block(0, &[1; 4]).unwrap();
+    // evicting block 0 fails, so block 1 is refused and block 0 is kept
+    assert!(cache.write_block(1, &[2; 4]).is_err());
+    assert_eq!(cache.dirty_blocks(), 1);
+    let mut buffer = [0; 4];
+    cache.read_block(0, &mut buffer).unwrap();
+    assert_eq!(buffer, [1; 4]);
+
+    failing.set(false);
+    cache.write_block(1, &[2; 4]).unwrap();
+    cache.flush().unwrap();
+    assert_eq!(cache.dirty_blocks(), 0);
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use utils::to_number;
+
+// names for addresses, from assembler and linker outputs
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    // the first name defined for an address is used for display
+    by_address: BTreeMap<u32, String>,
+    by_name: HashMap<String, u32>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn insert(&mut self, name: &str, address: u32) {
+        self.move_name(name, address);
+        self.by_address
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    pub fn merge(&mut self, other: &SymbolTable) {
+        for (name, address) in &other.by_name {
+            self.move_name(name, *address);
+        }
+        for (address, name) in &other.by_address {
+            self.by_address
+                .entry(*address)
+                .or_insert_with(|| name.clone());
+        }
+    }
+
+    // a redefined name no longer names its old address, another name there takes its place
+    fn move_name(&mut self, name: &str, address: u32) {
+        let old_address = match self.by_name.insert(name.to_string(), address) {
+            Some(old_address) if old_address != address => old_address,
+            _ => return,
+        };
+        if self.by_address.get(&old_address).map(|old| old.as_str()) != Some(name) {
+            return;
+        }
+        let alias = self
+            .by_name
+            .iter()
+            .filter(|&(_, other)| *other == old_address)
+            .map(|(alias, _)| alias)
+            .min()
+            .cloned();
+        match alias {
+            Some(alias) => self.by_address.insert(old_address, alias),
+            None => self.by_address.remove(&old_address),
+        };
+    }
+
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).cloned()
+    }
+
+    pub fn name(&self, address: u32) -> Option<&str> {
+        self.by_address.get(&address).map(|name| name.as_str())
+    }
+
+    // the closest symbol at or below the address, with the distance from it
+    pub fn nearest(&self, address: u32) -> Option<(&str, u32)> {
+        self.by_address
+            .range(..=address)
+            .next_back()
+            .map(|(symbol_address, name)| (name.as_str(), address - symbol_address))
+    }
+
+    // accepts numbers in any to_number() format, symbols and symbol+offset
+    pub fn resolve(&self, text: &str) -> Result<u32, String> {
+        if let Ok(address) = to_number::<u32>(text) {
+            return Ok(address);
+        }
+        let mut parts = text.splitn(2, '+');
+        let name = parts.next().unwrap();
+        let address = match self.address(name) {
+            Some(address) => address,
+            None => return Err(format!("unknown symbol {}", name)),
+        };
+        match parts.next() {
+            Some(offset) => match to_number::<u32>(offset) {
+                Ok(offset) => Ok(address.wrapping_add(offset)),
+                Err(err) => Err(format!("invalid offset {}: {}", offset, err)),
+            },
+            None => Ok(address),
+        }
+    }
+}
+
+fn hex_address(text: &str, line: usize) -> Result<u32, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    match u32::from_str_radix(digits, 16) {
+        Ok(address) => Ok(address),
+        Err(err) => Err(format!("line {}: invalid address {}: {}", line, text, err)),
+    }
+}
+
+// sym lines of ca65/ld65 debug files, both the id=,name=,val= and the older
+// name=,value= syntax, imports and symbols without value are skipped
+pub fn parse_ca65_dbg(text: &str) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim();
+        if !line.starts_with("sym") {
+            continue;
+        }
+        let mut name = None;
+        let mut value = None;
+        let mut kind = None;
+        for field in line[3..].trim().split(',') {
+            let mut parts = field.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let field_value = parts.next().unwrap_or("").trim();
+            match key {
+                "name" => name = Some(field_value.trim_matches('"')),
+                "val" | "value" => value = Some(field_value),
+                "type" => kind = Some(field_value),
+                _ => (),
+            }
+        }
+        if kind == Some("imp") {
+            continue;
+        }
+        if let (Some(name), Some(value)) = (name, value) {
+            symbols.insert(name, hex_address(value, index + 1)?);
+        }
+    }
+    Ok(symbols)
+}
+
+// VICE monitor label files (ld65 -Ln): al C:c000 .main
+pub fn parse_vice_labels(text: &str) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let mut parts = raw_line.split_whitespace();
+        match parts.next() {
+            Some("al") => (),
+            Some(_) | None => continue,
+        }
+        let (address, name) = match (parts.next(), parts.next()) {
+            (Some(address), Some(name)) => (address, name),
+            _ => return Err(format!("line {}: expected al <address> <label>", index + 1)),
+        };
+        // the address can be prefixed by the memory space
+        let address = match address.find(':') {
+            Some(colon) => &address[colon + 1..],
+            None => address,
+        };
+        symbols.insert(
+            name.trim_start_matches('.'),
+            hex_address(address, index + 1)?,
+        );
+    }
+    Ok(symbols)
+}
+
+// name = $addr lines, # and ; start comments
+pub fn parse_labels(text: &str) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let name = parts.next().unwrap().trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => return Err(format!("line {}: expected name = address", index + 1)),
+        };
+        match to_number::<u32>(value) {
+            Ok(address) => symbols.insert(name, address),
+            Err(err) => {
+                return Err(format!(
+                    "line {}: invalid address {}: {}",
+                    index + 1,
+                    value,
+                    err
+                ))
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+// the format is guessed from the content
+pub fn load_file<P: AsRef<Path>>(filename: P) -> Result<SymbolTable, String> {
+    let path = filename.as_ref();
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => return Err(format!("unable to read {}: {}", path.display(), err)),
+    };
+    let first_line = text
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or("");
+    let result = if first_line.starts_with("version") || first_line.starts_with("sym") {
+        parse_ca65_dbg(&text)
+    } else if first_line.starts_with("al ") {
+        parse_vice_labels(&text)
+    } else {
+        parse_labels(&text)
+    };
+    result.map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests;
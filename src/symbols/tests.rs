@@ -0,0 +1,70 @@
+use symbols::{parse_ca65_dbg, parse_labels, parse_vice_labels, SymbolTable};
+
+#[test]
+fn resolve_and_nearest() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("main", 0xc000);
+    symbols.insert("start", 0xc000);
+    symbols.insert("printstring", 0xc0a3);
+    assert_eq!(symbols.len(), 3);
+    assert_eq!(symbols.name(0xc000), Some("main"));
+    assert_eq!(symbols.resolve("start"), Ok(0xc000));
+    assert_eq!(symbols.resolve("printstring+2"), Ok(0xc0a5));
+    assert_eq!(symbols.resolve("$1234"), Ok(0x1234));
+    assert!(symbols.resolve("missing").is_err());
+    assert_eq!(symbols.nearest(0xc0a4), Some(("printstring", 1)));
+    assert_eq!(symbols.nearest(0xbfff), None);
+}
+
+#[test]
+fn redefined_name_leaves_its_address() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("main", 0xc000);
+    symbols.insert("start", 0xc000);
+    symbols.insert("loop", 0xc010);
+    symbols.insert("main", 0xc020);
+    assert_eq!(symbols.name(0xc000), Some("start"));
+    assert_eq!(symbols.name(0xc020), Some("main"));
+    symbols.insert("loop", 0xc030);
+    assert_eq!(symbols.name(0xc010), None);
+    assert_eq!(symbols.nearest(0xc015), Some(("start", 0x15)));
+
+    let mut other = SymbolTable::new();
+    other.insert("start", 0xc040);
+    symbols.merge(&other);
+    assert_eq!(symbols.name(0xc000), None);
+    assert_eq!(symbols.name(0xc040), Some("start"));
+}
+
+#[test]
+fn ca65_dbg() {
+    let symbols = parse_ca65_dbg(
+        "version\tmajor=2,minor=0\n\
+         seg\tid=0,name=\"CODE\",start=0x00C000,size=0x0100,addrsize=absolute,type=ro\n\
+         sym\tid=0,name=\"main\",addrsize=absolute,scope=0,def=1,val=0xC000,seg=0,type=lab\n\
+         sym\tid=1,name=\"printstring\",addrsize=absolute,scope=0,def=2,val=0xC0A3,type=lab\n\
+         sym\tid=2,name=\"external\",addrsize=absolute,scope=0,def=3,type=imp\n\
+         sym\tname=\"old\",value=0x0010,addrsize=zeropage,type=equate\n",
+    )
+    .unwrap();
+    assert_eq!(symbols.len(), 3);
+    assert_eq!(symbols.address("printstring"), Some(0xc0a3));
+    assert_eq!(symbols.address("old"), Some(0x10));
+    assert_eq!(symbols.address("external"), None);
+}
+
+#[test]
+fn vice_labels() {
+    let symbols = parse_vice_labels("al C:c000 .main\nal 00C0A3 .printstring\n").unwrap();
+    assert_eq!(symbols.address("main"), Some(0xc000));
+    assert_eq!(symbols.name(0xc0a3), Some("printstring"));
+    assert!(parse_vice_labels("al C:c000\n").is_err());
+}
+
+#[test]
+fn plain_labels() {
+    let symbols = parse_labels("; screen\nvideo = $4000\ncounter = 0x10\n").unwrap();
+    assert_eq!(symbols.address("video"), Some(0x4000));
+    assert_eq!(symbols.address("counter"), Some(0x10));
+    assert!(parse_labels("video $4000\n").is_err());
+}
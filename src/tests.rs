@@ -1,4 +1,10 @@
-use {Address, AddressBusIO, Data};
+use std::error::Error;
+use std::io;
+
+use memcontroller::MemoryController;
+use offset_address;
+use ram::Ram;
+use {Address, AddressBusExt, AddressBusIO, BusError, Data};
 
 struct TestAddressBusIO<T: Address, U: Data> {
     _address: T,
@@ -62,3 +68,80 @@ fn address_bus_io_u64_u32() {
     bus.write(0xffffffffaabbccdd, 0xaabbccdd);
     assert_eq!(bus.read(0xaabbccddffaaffbb), 0);
 }
+
+#[test]
+fn address_bus_ext_u16() {
+    let mut ram: Ram<u8> = Ram::new(16);
+    ram.write_u16_le(0x00u16, 0x1234);
+    ram.write_u16_be(0x02u16, 0x1234);
+    assert_eq!(ram.read(0x00u16), 0x34);
+    assert_eq!(ram.read(0x02u16), 0x12);
+    assert_eq!(ram.read_u16_le(0x00u16), 0x1234);
+    assert_eq!(ram.read_u16_be(0x02u16), 0x1234);
+}
+
+#[test]
+fn address_bus_ext_wraps_around() {
+    let mut ram: Ram<u8> = Ram::new(256);
+    ram.write_u16_le(0xffu8, 0x1234);
+    assert_eq!(ram.read(0xffu8), 0x34);
+    assert_eq!(ram.read(0x00u8), 0x12);
+}
+
+#[test]
+fn address_bus_ext_try_u16() {
+    let mut ram: Ram<u8> = Ram::new(4);
+    ram.write_u16_be(0x02u16, 0x1234);
+    assert_eq!(ram.try_read_u16_be(0x02u16).unwrap(), 0x1234);
+    assert_eq!(ram.try_read_u16_le(0x02u16).unwrap(), 0x3412);
+    assert!(ram.try_read_u16_le(0x03u16).is_err());
+}
+
+#[test]
+fn offset_address_u128() {
+    assert_eq!(offset_address(u128::MAX, 2), 1);
+    assert_eq!(offset_address(0x10u128, 4), 0x14);
+    assert_eq!(offset_address(0xffu8, 300), 0x2b);
+}
+
+#[test]
+fn address_bus_ext_block_longer_than_address_space() {
+    let mut ram: Ram<u8> = Ram::new(256);
+    let data: Vec<u8> = (0..300).map(|value| value as u8).collect();
+    ram.load(&data, 0x10u8).unwrap();
+    // the last 44 bytes wrapped over the first ones
+    assert_eq!(ram.read(0x10u8), 0x00);
+    assert_eq!(ram.read(0x3bu8), 0x2b);
+    assert_eq!(ram.read(0x3cu8), 0x2c);
+    let mut buffer = [0u8; 300];
+    ram.read_block(0x00u8, &mut buffer);
+    assert_eq!(buffer[256], buffer[0]);
+}
+
+#[test]
+fn address_bus_ext_block() {
+    let mut ram = Ram::new(16);
+    let mut memory_controller = MemoryController::new();
+    memory_controller.map(0x1000, 0x100f, &mut ram);
+    memory_controller.write_block(0x1004u16, &[1u8, 2, 3]);
+    let mut buffer = [0; 5];
+    memory_controller.read_block(0x1003, &mut buffer);
+    assert_eq!(buffer, [0, 1, 2, 3, 0]);
+}
+
+#[test]
+fn address_bus_ext_load() {
+    let mut ram: Ram<u8> = Ram::new(4);
+    assert!(ram.load(&[1, 2, 3], 0x0001u16).is_ok());
+    assert_eq!(ram.read_u16_le(0x0002u16), 0x0302);
+    assert!(ram.load(&[1, 2, 3], 0x0002u16).is_err());
+}
+
+#[test]
+fn bus_error_is_an_error() {
+    let err: Box<dyn Error> = Box::new(BusError::out_of_range(0x8000u16));
+    assert_eq!(err.to_string(), "address $8000 out of range");
+    assert!(err.source().is_none());
+    let err = BusError::from(io::Error::other("disk"));
+    assert!(err.source().is_some());
+}
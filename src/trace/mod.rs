@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+
+use {Address, AddressBusIO, BusError, Data};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    pub fn matches(self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BusEvent<T: Address, U: Data> {
+    pub address: T,
+    pub value: U,
+    pub access: Access,
+    pub cycle: u64,
+}
+
+pub type Callback<T, U> = Box<dyn FnMut(&BusEvent<T, U>)>;
+
+struct Watchpoint<T: Address, U: Data> {
+    handle: usize,
+    start: T,
+    end: T,
+    kind: WatchKind,
+    callback: Callback<T, U>,
+}
+
+// sits between a cpu and its bus, recording every access
+pub struct TracingBus<T: Address, U: Data, B: AddressBusIO<T, U>> {
+    bus: B,
+    events: VecDeque<BusEvent<T, U>>,
+    capacity: usize,
+    sink: Option<Callback<T, U>>,
+    watchpoints: Vec<Watchpoint<T, U>>,
+    handles: usize,
+    // bus cycles: one per access plus the wait states of the device
+    cycle: u64,
+    wait_states: u64,
+}
+
+impl<T: Address, U: Data, B: AddressBusIO<T, U>> TracingBus<T, U, B> {
+    // capacity is the number of events kept, 0 disables the ring buffer
+    pub fn new(bus: B, capacity: usize) -> TracingBus<T, U, B> {
+        TracingBus {
+            bus,
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            sink: None,
+            watchpoints: Vec::new(),
+            handles: 0,
+            cycle: 0,
+            wait_states: 0,
+        }
+    }
+
+    pub fn bus(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    pub fn events(&self) -> &VecDeque<BusEvent<T, U>> {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    // receives every event, even the ones dropped by the ring buffer
+    pub fn set_sink(&mut self, sink: Callback<T, U>) {
+        self.sink = Some(sink);
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    // allows hosts to keep the trace in sync with the cpu ticks
+    pub fn set_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    pub fn add_watchpoint(
+        &mut self,
+        start: T,
+        end: T,
+        kind: WatchKind,
+        callback: Callback<T, U>,
+    ) -> usize {
+        let handle = self.handles;
+        self.handles += 1;
+        self.watchpoints.push(Watchpoint {
+            handle,
+            start,
+            end,
+            kind,
+            callback,
+        });
+        handle
+    }
+
+    pub fn remove_watchpoint(&mut self, handle: usize) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.handle != handle);
+        self.watchpoints.len() != before
+    }
+
+    fn record(&mut self, address: T, value: U, access: Access) {
+        let event = BusEvent {
+            address,
+            value,
+            access,
+            cycle: self.cycle,
+        };
+        let wait_states = self.bus.take_wait_states();
+        self.wait_states += wait_states;
+        self.cycle += 1 + wait_states;
+
+        if self.capacity > 0 {
+            if self.events.len() == self.capacity {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+        if let Some(sink) = self.sink.as_mut() {
+            sink(&event);
+        }
+        for watchpoint in &mut self.watchpoints {
+            if address >= watchpoint.start
+                && address <= watchpoint.end
+                && watchpoint.kind.matches(access)
+            {
+                (watchpoint.callback)(&event);
+            }
+        }
+    }
+}
+
+impl<T: Address, U: Data, B: AddressBusIO<T, U>> AddressBusIO<T, U> for TracingBus<T, U, B> {
+    fn read(&mut self, address: T) -> U {
+        match self.try_read(address) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn write(&mut self, address: T, value: U) {
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
+        }
+    }
+
+    fn try_read(&mut self, address: T) -> Result<U, BusError> {
+        let value = self.bus.try_read(address)?;
+        self.record(address, value, Access::Read);
+        Ok(value)
+    }
+
+    fn try_write(&mut self, address: T, value: U) -> Result<(), BusError> {
+        self.bus.try_write(address, value)?;
+        self.record(address, value, Access::Write);
+        Ok(())
+    }
+
+    fn take_wait_states(&mut self) -> u64 {
+        let wait_states = self.wait_states;
+        self.wait_states = 0;
+        wait_states
+    }
+
+    fn peek(&self, address: T) -> Option<U> {
+        self.bus.peek(address)
+    }
+}
+
+#[cfg(test)]
+mod tests;
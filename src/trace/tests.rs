@@ -0,0 +1,79 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mos6502::MOS6502;
+use ram::Ram;
+use trace::{Access, BusEvent, TracingBus, WatchKind};
+use {AddressBusIO, Clock};
+
+#[test]
+fn record_lda_absolute() {
+    let mut ram = Ram::new(1024);
+    ram.fill(vec![0xad, 0x00, 0x02], 0);
+    ram.fill(vec![0x17], 0x200);
+    let mut cpu = MOS6502::new(TracingBus::new(ram, 16));
+    cpu.step();
+    let events: Vec<(u16, u8, Access)> = cpu
+        .bus()
+        .events()
+        .iter()
+        .map(|event| (event.address, event.value, event.access))
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (0x0000, 0xad, Access::Read),
+            (0x0001, 0x00, Access::Read),
+            (0x0002, 0x02, Access::Read),
+            (0x0200, 0x17, Access::Read),
+        ]
+    );
+    assert_eq!(cpu.bus().cycle(), 4);
+}
+
+#[test]
+fn ring_buffer_is_bounded() {
+    let mut bus = TracingBus::new(Ram::new(16), 2);
+    bus.write(0x00u8, 0x01u8);
+    bus.write(0x01, 0x02);
+    bus.read(0x00);
+    assert_eq!(bus.events().len(), 2);
+    assert_eq!(
+        bus.events()[0],
+        BusEvent {
+            address: 0x01,
+            value: 0x02,
+            access: Access::Write,
+            cycle: 1,
+        }
+    );
+}
+
+#[test]
+fn watchpoints_and_sink() {
+    let hits = Rc::new(RefCell::new(Vec::new()));
+    let total = Rc::new(RefCell::new(0));
+    let mut bus = TracingBus::new(Ram::new(16), 0);
+
+    let watch_hits = hits.clone();
+    let handle = bus.add_watchpoint(
+        0x04u8,
+        0x07,
+        WatchKind::Write,
+        Box::new(move |event: &BusEvent<u8, u8>| watch_hits.borrow_mut().push(event.address)),
+    );
+    let sink_total = total.clone();
+    bus.set_sink(Box::new(move |_: &BusEvent<u8, u8>| {
+        *sink_total.borrow_mut() += 1
+    }));
+
+    bus.write(0x03, 0x01);
+    bus.write(0x05, 0x01);
+    bus.read(0x05);
+    assert!(bus.remove_watchpoint(handle));
+    bus.write(0x06, 0x01);
+
+    assert_eq!(*hits.borrow(), vec![0x05]);
+    assert_eq!(*total.borrow(), 4);
+    assert!(bus.events().is_empty());
+}
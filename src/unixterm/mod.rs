@@ -3,9 +3,26 @@ use std::io::Write;
 use std::io::{stderr, stdin, stdout, Stderr, Stdout};
 use std::process;
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
-use AddressBusIO;
+use {AddressBusIO, BusError};
+
+// the code written to the exit register, for hosts that shut down on their own
+#[derive(Clone, Default)]
+pub struct ExitRequest {
+    code: Arc<Mutex<Option<i32>>>,
+}
+
+impl ExitRequest {
+    pub fn get(&self) -> Option<i32> {
+        *self.code.lock().unwrap()
+    }
+
+    fn set(&self, code: i32) {
+        *self.code.lock().unwrap() = Some(code);
+    }
+}
 
 pub struct UnixTerm {
     stdout: Stdout,
@@ -16,6 +33,9 @@ pub struct UnixTerm {
 
     channel_data: (Sender<u8>, Receiver<u8>),
     channel_command: Sender<u8>,
+
+    // the process exits on the exit register unless deferred
+    exit_request: Option<ExitRequest>,
 }
 
 impl UnixTerm {
@@ -29,6 +49,7 @@ impl UnixTerm {
             last_stderr: 0,
             channel_data: channel(),
             channel_command: channel_command.0.clone(),
+            exit_request: None,
         };
 
         let sender = term.channel_data.0.clone();
@@ -53,6 +74,13 @@ impl UnixTerm {
 
         term
     }
+
+    // the exit register no longer ends the process, the host polls the request
+    pub fn defer_exit(&mut self) -> ExitRequest {
+        self.exit_request
+            .get_or_insert_with(ExitRequest::default)
+            .clone()
+    }
 }
 
 impl AddressBusIO<u8, u8> for UnixTerm {
@@ -71,23 +99,31 @@ impl AddressBusIO<u8, u8> for UnixTerm {
     }
 
     fn write(&mut self, address: u8, value: u8) {
+        if let Err(err) = self.try_write(address, value) {
+            panic!("{}", err);
+        }
+    }
+
+    fn try_write(&mut self, address: u8, value: u8) -> Result<(), BusError> {
         let buffer = [value; 1];
         match address {
             0x01 => {
-                self.stdout.write_all(&buffer).unwrap();
-                self.stdout.flush().unwrap();
+                self.stdout.write_all(&buffer)?;
+                self.stdout.flush()?;
                 self.last_stdout = value;
             }
             0x02 => {
-                self.stderr.write_all(&buffer).unwrap();
-                self.stderr.flush().unwrap();
+                self.stderr.write_all(&buffer)?;
+                self.stderr.flush()?;
                 self.last_stderr = value;
             }
-            0x03 => {
-                process::exit(i32::from(value));
-            }
+            0x03 => match self.exit_request.as_ref() {
+                Some(exit_request) => exit_request.set(i32::from(value)),
+                None => process::exit(i32::from(value)),
+            },
             _ => {}
         }
+        Ok(())
     }
 }
 
@@ -109,4 +145,13 @@ mod tests {
         term.write(0x02 as u8, 22 as u8);
         assert_eq!(term.read(0x02 as u8), 22 as u8);
     }
+
+    #[test]
+    fn deferred_exit() {
+        let mut term = UnixTerm::new();
+        let exit_request = term.defer_exit();
+        assert_eq!(exit_request.get(), None);
+        term.write(0x03u8, 3u8);
+        assert_eq!(exit_request.get(), Some(3));
+    }
 }
@@ -1,4 +1,8 @@
 use std::num::ParseIntError;
+#[cfg(test)]
+use std::path::PathBuf;
+#[cfg(test)]
+use std::{env, process};
 use Address;
 
 pub fn to_number<T: Address<FromStrRadixErr = ParseIntError>>(
@@ -26,3 +30,9 @@ pub fn to_number<T: Address<FromStrRadixErr = ParseIntError>>(
 
     T::from_str_radix(string, 10)
 }
+
+// a file in the shared temp dir, unique per test name and per process
+#[cfg(test)]
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("impostor_{}_{}", name, process::id()))
+}